@@ -0,0 +1,192 @@
+use raylib::math::Vector3;
+use rust_rt::rendering::Ray;
+use rust_rt::scene::bvh::{BVHBuilder, LinearSettings, SahSettings, BVH};
+use rust_rt::scene::mesh::Mesh;
+use rust_rt::scene::AABB;
+use rust_rt::utils::{rand_unit_vec, rand_vec3, seed_rng};
+use std::fs;
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: bvh_stats [options] [model.obj...]
+
+Builds the BVH of every model with each builder and prints the shape of the
+trees, so builders can be compared. All trees are priced with the same SAH
+costs and timed on the same random rays through the model, traced on a
+single thread. Models default to models/*.obj.
+
+Options:
+        --bins <n>                candidate SAH splits per axis (default: 16)
+        --leaf-size <n>           largest leaf the SAH builder may keep (default: 8)
+        --traversal-cost <c>      cost of testing a node's box (default: 1)
+        --intersection-cost <c>   cost of testing a triangle (default: 2)
+        --linear-leaf-size <n>    largest leaf of the linear builder (default: 4)
+        --sah-levels <n>          levels the linear builder splits with SAH (default: 0)
+        --rays <n>                rays traced per tree (default: 1000000)
+        --help                    print this message";
+
+struct Options {
+    models: Vec<String>,
+    sah: SahSettings,
+    linear: LinearSettings,
+    rays: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            models: Vec::new(),
+            sah: SahSettings::default(),
+            linear: LinearSettings::default(),
+            rays: 1_000_000,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let models = if opts.models.is_empty() {
+        match bundled_models() {
+            Ok(models) => models,
+            Err(err) => {
+                eprintln!("error: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        opts.models.clone()
+    };
+
+    let builders = [
+        ("midpoint", BVHBuilder::Midpoint),
+        ("sah", BVHBuilder::Sah(opts.sah)),
+        ("linear", BVHBuilder::Linear(opts.linear)),
+    ];
+
+    for path in &models {
+        let tris = match Mesh::load_triangles(path) {
+            Ok(tris) => tris,
+            Err(err) => {
+                eprintln!("error: {err}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+        let rays = random_rays(&AABB::from_tris(&tris), opts.rays);
+
+        println!("\n{path}: {} triangles", tris.len());
+        println!(
+            "  {:<10} {:>10} {:>8} {:>8} {:>6} {:>9} {:>9} {:>9}",
+            "builder", "build ms", "nodes", "leaves", "depth", "max leaf", "SAH cost", "Mrays/s"
+        );
+
+        for (name, builder) in builders {
+            let mut bvh = BVH::new(tris.clone());
+            let build_time = bvh.build_with(builder);
+
+            let start = Instant::now();
+            let hits = rays
+                .iter()
+                .filter(|ray| bvh.intersect(ray).is_some())
+                .count();
+            let trace_time = start.elapsed();
+            // keeps the traversal from being optimized away
+            std::hint::black_box(hits);
+
+            let stats = bvh.stats(&opts.sah);
+            println!(
+                "  {:<10} {:>10.1} {:>8} {:>8} {:>6} {:>9} {:>9.2} {:>9.2}",
+                name,
+                build_time.as_secs_f64() * 1000f64,
+                stats.nodes,
+                stats.leaves,
+                stats.depth,
+                stats.max_leaf_size,
+                stats.sah_cost,
+                rays.len() as f64 / trace_time.as_secs_f64() / 1e6f64
+            );
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Rays from a sphere around `aabb` towards random points inside it, the same
+/// ones every run.
+fn random_rays(aabb: &AABB, count: usize) -> Vec<Ray> {
+    seed_rng(1);
+    let center = aabb.center();
+    let half_extent = (aabb.max - aabb.min) * 0.5f32;
+    let radius = half_extent.length().max(f32::MIN_POSITIVE);
+
+    (0..count)
+        .map(|_| {
+            let origin = center + rand_unit_vec() * (2f32 * radius);
+            let r = rand_vec3();
+            let target = center
+                + Vector3::new(
+                    r.x * half_extent.x,
+                    r.y * half_extent.y,
+                    r.z * half_extent.z,
+                );
+            Ray::new(origin, (target - origin).normalized())
+        })
+        .collect()
+}
+
+fn bundled_models() -> Result<Vec<String>, String> {
+    let entries = fs::read_dir("models").map_err(|err| format!("Failed to read models: {err}"))?;
+    let mut models: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "obj"))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    models.sort();
+    Ok(models)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut opts = Options::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            return Ok(None);
+        }
+        if !arg.starts_with("--") {
+            opts.models.push(arg);
+            continue;
+        }
+
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+
+        match arg.as_str() {
+            "--bins" => opts.sah.bins = parse_num(&arg, &value()?)?,
+            "--leaf-size" => opts.sah.max_leaf_size = parse_num(&arg, &value()?)?,
+            "--traversal-cost" => opts.sah.traversal_cost = parse_num(&arg, &value()?)?,
+            "--intersection-cost" => opts.sah.intersection_cost = parse_num(&arg, &value()?)?,
+            "--linear-leaf-size" => opts.linear.max_leaf_size = parse_num(&arg, &value()?)?,
+            "--sah-levels" => opts.linear.sah_levels = parse_num(&arg, &value()?)?,
+            "--rays" => opts.rays = parse_num(&arg, &value()?)?,
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+
+    Ok(Some(opts))
+}
+
+fn parse_num<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for {arg}"))
+}
@@ -0,0 +1,475 @@
+use raylib::math::Vector3;
+use rust_rt::rendering::{
+    add_cryptomattes, Aov, AovBuffers, Denoiser, ExrSampleType, ExrWriter, Framebuffer,
+    ImageFormat, ObjectMask, Projection, Renderer, ToneMapper,
+};
+use rust_rt::scene::models::Scene;
+use rust_rt::scene::presets::init_sphere_scene;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: render [options]
+
+Renders a scene offline, without opening a window, and writes the result to disk.
+Options given here override the [render] and [camera] values from the scene file.
+
+Options:
+        --scene <path>        scene file to render (default: built in dragon scene)
+    -o, --output <path>       output image, format picked by extension (default: render.png)
+        --16bit               write 16 bits per channel (png, tiff, half float exr)
+        --tonemap <name>      clamp, reinhard, extended-reinhard, hable or aces
+        --white <l>           luminance mapped to white by extended-reinhard (default: 4)
+        --dither              dither 8 and 16 bit output to hide banding
+        --denoise             denoise the image guided by normal, albedo and depth
+        --aov <names>         comma separated AOVs to write: depth, position, normal,
+                              geometric_normal, albedo, object_id, material_id, uv,
+                              direct, indirect. Layers of an exr output, separate
+                              <output>.<aov>.exr files otherwise
+        --cryptomatte         add Cryptomatte object and material layers to an exr output
+        --mask <path.exr>     also write integer object and material ids with coverage
+                              mattes for every object and material, and false color
+                              previews next to it as <path>.objects.png and
+                              <path>.materials.png
+    -w, --width <px>          image width
+    -h, --height <px>         image height
+    -s, --samples <n>         samples per pixel
+    -b, --bounces <n>         maximum path depth
+        --seed <n>            seed for reproducible renders
+        --position <x,y,z>    camera position
+        --yaw <deg>           camera yaw
+        --pitch <deg>         camera pitch
+        --look-at <x,y,z>     point the camera at a target instead of using yaw and pitch
+        --up <x,y,z>          camera up vector
+        --roll <deg>          camera roll
+        --fov <deg>           vertical field of view
+        --aperture <r>        lens radius, 0 for a pinhole camera
+        --f-number <n>        f-stop, opens the lens to match unless --aperture is given
+        --shutter <s>         shutter time in seconds
+        --iso <n>             sensor sensitivity
+        --ev <stops>          exposure compensation
+        --white-balance <k>   color temperature in kelvin that comes out white
+        --focus <dist>        focus distance
+        --autofocus           focus on whatever is in the center of the image
+        --projection <name>   perspective, orthographic, fisheye or equirectangular
+        --ortho-width <w>     world space width of an orthographic view (default: 10)
+        --help                print this message";
+
+#[derive(Default)]
+struct Options {
+    scene: Option<String>,
+    output: Option<String>,
+    sixteen_bit: bool,
+    tonemap: Option<String>,
+    white: Option<f32>,
+    dither: bool,
+    denoise: bool,
+    aovs: Vec<String>,
+    cryptomatte: bool,
+    mask: Option<String>,
+    width: Option<usize>,
+    height: Option<usize>,
+    samples: Option<u32>,
+    bounces: Option<i32>,
+    seed: Option<u64>,
+    position: Option<Vector3>,
+    yaw: Option<f32>,
+    pitch: Option<f32>,
+    look_at: Option<Vector3>,
+    up: Option<Vector3>,
+    roll: Option<f32>,
+    fov: Option<f32>,
+    aperture: Option<f32>,
+    f_number: Option<f32>,
+    shutter: Option<f32>,
+    iso: Option<f32>,
+    ev: Option<f32>,
+    white_balance: Option<f32>,
+    focus: Option<f32>,
+    autofocus: bool,
+    projection: Option<String>,
+    ortho_width: Option<f32>,
+}
+
+fn main() -> ExitCode {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let output = opts.output.clone().unwrap_or(String::from("render.png"));
+    if let Some(mask) = &opts.mask {
+        if ImageFormat::from_path(mask) != Some(ImageFormat::Exr) {
+            eprintln!("error: --mask requires an exr output");
+            return ExitCode::from(2);
+        }
+    }
+    let format = match output_format(&output, opts.sixteen_bit) {
+        Ok(format) => format,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let start = Instant::now();
+    let mut scene = match &opts.scene {
+        Some(path) => match Scene::from_file_with_report(path) {
+            Ok((scene, report)) => {
+                for mesh in &report.meshes {
+                    println!(
+                        "Built BVH over {} triangles of {} in {:.1} ms",
+                        mesh.triangles,
+                        mesh.path.display(),
+                        mesh.build_time.as_secs_f64() * 1000f64
+                    );
+                }
+                scene
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            let mut scene = Scene::new();
+            init_sphere_scene(&mut scene);
+            scene
+        }
+    };
+    println!(
+        "Loaded scene and built BVHs in {:.2}s",
+        start.elapsed().as_secs_f32()
+    );
+
+    let settings = &mut scene.settings;
+    settings.width = opts.width.unwrap_or(settings.width);
+    settings.height = opts.height.unwrap_or(settings.height);
+    settings.samples = opts.samples.unwrap_or(settings.samples);
+    settings.bounces = opts.bounces.unwrap_or(settings.bounces);
+    settings.seed = opts.seed.or(settings.seed);
+    settings.dither |= opts.dither;
+    settings.denoise |= opts.denoise;
+    settings.cryptomatte |= opts.cryptomatte;
+    if settings.cryptomatte && !matches!(format, ImageFormat::Exr | ImageFormat::ExrHalf) {
+        eprintln!("error: cryptomatte requires an exr output");
+        return ExitCode::from(2);
+    }
+    settings.aovs.extend(opts.aovs.iter().cloned());
+    if let Some(name) = settings.aovs.iter().find(|n| Aov::from_name(n).is_none()) {
+        eprintln!("error: unknown AOV '{name}'");
+        return ExitCode::from(2);
+    }
+
+    if settings.width == 0 || settings.height == 0 {
+        eprintln!("error: width and height must be greater than 0");
+        return ExitCode::from(2);
+    }
+    if settings.samples == 0 {
+        eprintln!("error: samples must be greater than 0");
+        return ExitCode::from(2);
+    }
+
+    let mut cam = scene.camera.clone();
+    cam.position = opts.position.unwrap_or(cam.position);
+    if opts.yaw.is_some() || opts.pitch.is_some() {
+        cam.yaw = opts.yaw.unwrap_or(cam.yaw);
+        cam.pitch = opts.pitch.unwrap_or(cam.pitch);
+        cam.update_direction();
+    }
+    cam.up = opts.up.unwrap_or(cam.up);
+    if let Some(target) = opts.look_at {
+        cam.set_look_at(target, cam.up);
+    }
+    cam.roll = opts.roll.unwrap_or(cam.roll);
+    cam.fov = opts.fov.unwrap_or(cam.fov);
+    if let Some(f_number) = opts.f_number {
+        cam.set_f_number(f_number);
+    }
+    cam.aperture = opts.aperture.unwrap_or(cam.aperture);
+    let exposure = &mut cam.exposure;
+    exposure.shutter = opts.shutter.unwrap_or(exposure.shutter);
+    exposure.iso = opts.iso.unwrap_or(exposure.iso);
+    exposure.compensation = opts.ev.unwrap_or(exposure.compensation);
+    exposure.white_balance = opts.white_balance.unwrap_or(exposure.white_balance);
+    if exposure.shutter <= 0f32 || exposure.f_number <= 0f32 || exposure.iso <= 0f32 {
+        eprintln!("error: shutter, f-number and iso must be greater than 0");
+        return ExitCode::from(2);
+    }
+    cam.focus_distance = opts.focus.unwrap_or(cam.focus_distance);
+    match parse_projection(&opts, cam.projection) {
+        Ok(projection) => cam.projection = projection,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(2);
+        }
+    }
+    let mut tone_mapping = scene.settings.tone_mapping();
+    match parse_tone_mapper(&opts, tone_mapping.operator) {
+        Ok(operator) => tone_mapping.operator = operator,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(2);
+        }
+    }
+
+    if opts.autofocus {
+        match cam.autofocus(&scene) {
+            Some(dist) => println!("Focused at {dist:.3}"),
+            None => eprintln!("warning: nothing to focus on in the center of the image"),
+        }
+    }
+
+    let settings = scene.settings.clone();
+    let mut renderer = Renderer::new(&scene);
+    renderer.num_bounces = settings.bounces;
+    renderer.seed = settings.seed;
+
+    let mut framebuf = Framebuffer::new(settings.width, settings.height);
+    let requested_aovs = settings.aovs();
+    let mut aov_list = requested_aovs.clone();
+    if settings.denoise {
+        aov_list.extend(Denoiser::AOVS);
+    }
+    let mut aovs = AovBuffers::new(settings.width, settings.height, &aov_list);
+
+    println!(
+        "Rendering {}x{} at {} spp, {} bounces",
+        settings.width, settings.height, settings.samples, settings.bounces
+    );
+    let start = Instant::now();
+    renderer.render_full_aovs(
+        settings.width,
+        settings.height,
+        &mut framebuf,
+        &mut aovs,
+        settings.samples,
+        &mut cam,
+    );
+    println!("Rendered in {:.2}s", start.elapsed().as_secs_f32());
+
+    if settings.denoise {
+        let start = Instant::now();
+        framebuf = Denoiser::default().denoise(&framebuf, 1f32, &aovs);
+        println!("Denoised in {:.2}s", start.elapsed().as_secs_f32());
+    }
+    aovs.retain(|aov| requested_aovs.contains(&aov));
+
+    framebuf.expose(cam.exposure.factor());
+    aovs.expose(cam.exposure.factor());
+    let mask = if settings.cryptomatte || opts.mask.is_some() {
+        let start = Instant::now();
+        let mut mask = ObjectMask::new(settings.width, settings.height);
+        for _ in 0..settings.samples {
+            renderer.render_object_mask(settings.width, settings.height, &mut mask, &mut cam);
+        }
+        println!("Rendered mask in {:.2}s", start.elapsed().as_secs_f32());
+        Some(mask)
+    } else {
+        None
+    };
+
+    let saved = match format {
+        ImageFormat::Exr | ImageFormat::ExrHalf if !aovs.is_empty() || settings.cryptomatte => {
+            let sample_type = if format == ImageFormat::Exr {
+                ExrSampleType::Float
+            } else {
+                ExrSampleType::Half
+            };
+            let mut writer = ExrWriter::new(settings.width, settings.height);
+            writer.add_layer("", &framebuf, 1f32, sample_type);
+            aovs.add_exr_layers(&mut writer, 1f32, sample_type);
+            if let (true, Some(mask)) = (settings.cryptomatte, &mask) {
+                add_cryptomattes(&mut writer, mask, &scene);
+            }
+            writer.write(&output).map_err(|err| err.to_string())
+        }
+        _ => framebuf
+            .save_as(&output, format, 1f32, Vector3::one(), &tone_mapping)
+            .map_err(|err| err.to_string()),
+    };
+    if let Err(err) = saved {
+        eprintln!("error: failed to write {output}: {err}");
+        return ExitCode::FAILURE;
+    }
+    println!("Wrote {output}");
+
+    if !matches!(format, ImageFormat::Exr | ImageFormat::ExrHalf) {
+        for (aov, buffer) in aovs.iter() {
+            let path = Path::new(&output).with_extension(format!("{}.exr", aov.name()));
+            if let Err(err) = buffer.save_exr(&path, ExrSampleType::Float, 1f32) {
+                eprintln!("error: failed to write {}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+            println!("Wrote {}", path.display());
+        }
+    }
+
+    if let (Some(path), Some(mask)) = (&opts.mask, &mask) {
+        let mut writer = ExrWriter::new(settings.width, settings.height);
+        mask.add_exr_layers(&mut writer, ExrSampleType::Float);
+        if let Err(err) = writer.write(path) {
+            eprintln!("error: failed to write {path}: {err}");
+            return ExitCode::FAILURE;
+        }
+        println!("Wrote {path}");
+
+        for (name, preview) in [
+            ("objects", mask.object_preview()),
+            ("materials", mask.material_preview()),
+        ] {
+            let preview_path = Path::new(path).with_extension(format!("{name}.png"));
+            if let Err(err) = preview.save(&preview_path) {
+                eprintln!("error: failed to write {}: {err}", preview_path.display());
+                return ExitCode::FAILURE;
+            }
+            println!("Wrote {}", preview_path.display());
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn output_format(output: &str, sixteen_bit: bool) -> Result<ImageFormat, String> {
+    match (ImageFormat::from_path(output), sixteen_bit) {
+        (Some(ImageFormat::Png), true) => Ok(ImageFormat::Png16),
+        (Some(ImageFormat::Tiff), true) => Ok(ImageFormat::Tiff16),
+        (Some(ImageFormat::Exr), true) => Ok(ImageFormat::ExrHalf),
+        (Some(_), true) => Err(String::from("--16bit requires a png, tiff or exr output")),
+        (Some(format), false) => Ok(format),
+        (None, _) => Err(format!("unsupported output format for {output}")),
+    }
+}
+
+/// The tone mapper asked for on the command line, `current` if none was.
+fn parse_tone_mapper(opts: &Options, current: ToneMapper) -> Result<ToneMapper, String> {
+    let white = match (opts.white, current) {
+        (Some(white), _) => white,
+        (None, ToneMapper::ExtendedReinhard { white }) => white,
+        (None, _) => 4f32,
+    };
+
+    match opts.tonemap.as_deref() {
+        None if opts.white.is_some() => Ok(ToneMapper::ExtendedReinhard { white }),
+        None => Ok(current),
+        Some("clamp") => Ok(ToneMapper::Clamp),
+        Some("reinhard") => Ok(ToneMapper::Reinhard),
+        Some("extended-reinhard") => Ok(ToneMapper::ExtendedReinhard { white }),
+        Some("hable") => Ok(ToneMapper::Hable),
+        Some("aces") => Ok(ToneMapper::AcesFitted),
+        Some(name) => Err(format!("unknown tone mapper '{name}'")),
+    }
+}
+
+/// The projection asked for on the command line, `current` if none was.
+fn parse_projection(opts: &Options, current: Projection) -> Result<Projection, String> {
+    let width = match (opts.ortho_width, current) {
+        (Some(width), _) => width,
+        (None, Projection::Orthographic { width }) => width,
+        (None, _) => 10f32,
+    };
+
+    match opts.projection.as_deref() {
+        None if opts.ortho_width.is_some() => Ok(Projection::Orthographic { width }),
+        None => Ok(current),
+        Some("perspective") => Ok(Projection::Perspective),
+        Some("orthographic") => Ok(Projection::Orthographic { width }),
+        Some("fisheye") => Ok(Projection::Fisheye),
+        Some("equirectangular") => Ok(Projection::Equirectangular),
+        Some(name) => Err(format!("unknown projection '{name}'")),
+    }
+}
+
+/// Returns `Ok(None)` when help was requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut opts = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => return Ok(None),
+            "--16bit" => {
+                opts.sixteen_bit = true;
+                continue;
+            }
+            "--dither" => {
+                opts.dither = true;
+                continue;
+            }
+            "--denoise" => {
+                opts.denoise = true;
+                continue;
+            }
+            "--cryptomatte" => {
+                opts.cryptomatte = true;
+                continue;
+            }
+            "--autofocus" => {
+                opts.autofocus = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+
+        match arg.as_str() {
+            "--scene" => opts.scene = Some(value()?),
+            "-o" | "--output" => opts.output = Some(value()?),
+            "--aov" => opts
+                .aovs
+                .extend(value()?.split(',').map(|name| name.trim().to_string())),
+            "--mask" => opts.mask = Some(value()?),
+            "--tonemap" => opts.tonemap = Some(value()?),
+            "--white" => opts.white = Some(parse_num(&arg, &value()?)?),
+            "-w" | "--width" => opts.width = Some(parse_num(&arg, &value()?)?),
+            "-h" | "--height" => opts.height = Some(parse_num(&arg, &value()?)?),
+            "-s" | "--samples" => opts.samples = Some(parse_num(&arg, &value()?)?),
+            "-b" | "--bounces" => opts.bounces = Some(parse_num(&arg, &value()?)?),
+            "--seed" => opts.seed = Some(parse_num(&arg, &value()?)?),
+            "--position" => opts.position = Some(parse_vec3(&arg, &value()?)?),
+            "--yaw" => opts.yaw = Some(parse_num(&arg, &value()?)?),
+            "--pitch" => opts.pitch = Some(parse_num(&arg, &value()?)?),
+            "--look-at" => opts.look_at = Some(parse_vec3(&arg, &value()?)?),
+            "--up" => opts.up = Some(parse_vec3(&arg, &value()?)?),
+            "--roll" => opts.roll = Some(parse_num(&arg, &value()?)?),
+            "--fov" => opts.fov = Some(parse_num(&arg, &value()?)?),
+            "--aperture" => opts.aperture = Some(parse_num(&arg, &value()?)?),
+            "--f-number" => opts.f_number = Some(parse_num(&arg, &value()?)?),
+            "--shutter" => opts.shutter = Some(parse_num(&arg, &value()?)?),
+            "--iso" => opts.iso = Some(parse_num(&arg, &value()?)?),
+            "--ev" => opts.ev = Some(parse_num(&arg, &value()?)?),
+            "--white-balance" => opts.white_balance = Some(parse_num(&arg, &value()?)?),
+            "--focus" => opts.focus = Some(parse_num(&arg, &value()?)?),
+            "--projection" => opts.projection = Some(value()?),
+            "--ortho-width" => opts.ortho_width = Some(parse_num(&arg, &value()?)?),
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+
+    Ok(Some(opts))
+}
+
+fn parse_num<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for {arg}"))
+}
+
+fn parse_vec3(arg: &str, value: &str) -> Result<Vector3, String> {
+    let parts = value
+        .split(',')
+        .map(|p| parse_num(arg, p.trim()))
+        .collect::<Result<Vec<f32>, String>>()?;
+
+    match parts[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!("expected x,y,z for {arg}, got '{value}'")),
+    }
+}
@@ -1,21 +1,35 @@
 use raylib::prelude::*;
-use rust_rt::math::Transform;
+use rust_rt::rendering::Aov;
+use rust_rt::rendering::AovBuffers;
+use rust_rt::rendering::Denoiser;
 use rust_rt::rendering::Framebuffer;
+use rust_rt::rendering::ObjectMask;
 use rust_rt::rendering::RayCamera;
 use rust_rt::rendering::Renderer;
-use rust_rt::rendering::{EmissiveMaterial, LambertianMaterial, MetalMaterial, RTMaterial};
-use rust_rt::scene::mesh::Mesh;
-use rust_rt::scene::models::{Scene, SceneObject};
-use rust_rt::scene::sphere::Sphere;
-use rust_rt::scene::Plane;
-use std::f32::consts::PI;
-use std::{ffi::CString, sync::Arc};
+use rust_rt::rendering::ToneMapper;
+use rust_rt::scene::description::{CameraDescription, ToneMapperDescription};
+use rust_rt::scene::models::Scene;
+use rust_rt::scene::presets::init_sphere_scene;
+use std::ffi::CString;
+use std::path::Path;
 
 fn main() {
     const HEIGHT: i32 = 500;
     const WIDTH: i32 = 16 * HEIGHT / 9;
     let mut res_scale: f32 = 0.2f32;
 
+    let scene = match std::env::args().nth(1) {
+        Some(path) => Scene::from_file(&path).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }),
+        None => {
+            let mut scene = Scene::new();
+            init_sphere_scene(&mut scene);
+            scene
+        }
+    };
+
     let (mut rl, thread) = init()
         .size(WIDTH, HEIGHT)
         .resizable()
@@ -26,13 +40,15 @@ fn main() {
 
     let img = Image::gen_image_color(WIDTH, HEIGHT, Color::RED);
 
-    let mut cam = RayCamera::new(Vector3::new(0f32, 2f32, 0f32));
+    let mut cam = scene.camera.clone();
+    let mut tone_mapping = scene.settings.tone_mapping();
     let mut framebuf = Framebuffer::new(WIDTH as usize, HEIGHT as usize);
-    let mut scene = Scene::new();
-
-    init_sphere_scene(&mut scene);
+    let mut aovs = AovBuffers::new(WIDTH as usize, HEIGHT as usize, &Denoiser::AOVS);
+    let mut denoise = scene.settings.denoise;
+    let denoiser = Denoiser::default();
 
     let mut renderer = Renderer::new(&scene);
+    renderer.num_bounces = scene.settings.bounces;
 
     let mut tex = rl
         .load_texture_from_image(&thread, &img)
@@ -55,6 +71,7 @@ fn main() {
                 (s_width as f32 * res_scale) as usize,
                 (s_height as f32 * res_scale) as usize,
             );
+            aovs = AovBuffers::new(framebuf.width, framebuf.height, &Denoiser::AOVS);
             tex = rl
                 .load_texture_from_image(
                     &thread,
@@ -69,22 +86,84 @@ fn main() {
             prev_scale = res_scale;
         }
 
+        if rl.is_key_pressed(KeyboardKey::KEY_F) && cam.autofocus(&scene).is_some() {
+            renderer.reset();
+            framebuf.clear();
+            aovs.clear();
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_T) {
+            tone_mapping.operator = next_tone_mapper(tone_mapping.operator);
+            println!("Tone mapping: {:?}", tone_mapping.operator);
+        }
+
         if prev_cam_dir != cam.direction || prev_cam_pos != cam.position {
             renderer.reset();
             framebuf.clear();
+            aovs.clear();
             prev_cam_dir = cam.direction;
             prev_cam_pos = cam.position;
         }
 
         if continue_rendering {
             if rl.is_key_down(KeyboardKey::KEY_N) {
-                renderer.render_normals(framebuf.width, framebuf.height, &mut framebuf, &mut cam);
-                tex.update_texture(&framebuf.to_bytes());
+                let mut normals = AovBuffers::new(framebuf.width, framebuf.height, &[Aov::Normal]);
+                renderer.render_sample_surface_aovs(
+                    framebuf.width,
+                    framebuf.height,
+                    &mut normals,
+                    &mut cam,
+                );
+                if let Some(normal) = normals.get(Aov::Normal) {
+                    tex.update_texture(&normal.to_bytes());
+                }
+                renderer.reset();
+                framebuf.clear();
+                aovs.clear();
+            } else if rl.is_key_down(KeyboardKey::KEY_B) {
+                let mut nodes = AovBuffers::new(framebuf.width, framebuf.height, &[Aov::BvhNodes]);
+                renderer.render_sample_surface_aovs(
+                    framebuf.width,
+                    framebuf.height,
+                    &mut nodes,
+                    &mut cam,
+                );
+                if let Some(nodes) = nodes.get(Aov::BvhNodes) {
+                    // scaled so the busiest pixel is white
+                    let max = nodes.data.iter().fold(1f32, |max, v| max.max(v.x));
+                    tex.update_texture(&nodes.to_bytes_s(max));
+                }
                 renderer.reset();
                 framebuf.clear();
+                aovs.clear();
+            } else if rl.is_key_down(KeyboardKey::KEY_M) {
+                let mut mask = ObjectMask::new(framebuf.width, framebuf.height);
+                renderer.render_object_mask(framebuf.width, framebuf.height, &mut mask, &mut cam);
+                tex.update_texture(&mask.object_preview().to_bytes());
+                renderer.reset();
+                framebuf.clear();
+                aovs.clear();
+            } else if denoise {
+                renderer.render_sample_aovs(
+                    framebuf.width,
+                    framebuf.height,
+                    &mut framebuf,
+                    &mut aovs,
+                    &mut cam,
+                );
+                let denoised = denoiser.denoise(&framebuf, renderer.num_samples as f32, &aovs);
+                tex.update_texture(&denoised.to_bytes_mapped(
+                    1f32,
+                    cam.exposure.factor(),
+                    &tone_mapping,
+                ));
             } else {
                 renderer.render_sample(framebuf.width, framebuf.height, &mut framebuf, &mut cam);
-                tex.update_texture(&framebuf.to_bytes_s(renderer.num_samples as f32));
+                tex.update_texture(&framebuf.to_bytes_mapped(
+                    renderer.num_samples as f32,
+                    cam.exposure.factor(),
+                    &tone_mapping,
+                ));
             }
         }
 
@@ -120,6 +199,7 @@ fn main() {
         ) {
             renderer.reset();
             framebuf.clear();
+            aovs.clear();
         }
 
         if d.gui_button(
@@ -129,6 +209,31 @@ fn main() {
             continue_rendering = !continue_rendering;
         }
 
+        if d.gui_button(
+            Rectangle::new(500f32, (s_height - 50) as f32, 100f32, 50f32),
+            Some(CString::new("Toggle Denoise").unwrap().as_c_str()),
+        ) {
+            denoise = !denoise;
+            renderer.reset();
+            framebuf.clear();
+            aovs.clear();
+        }
+
+        if d.gui_button(
+            Rectangle::new(400f32, (s_height - 50) as f32, 100f32, 50f32),
+            Some(CString::new("Save Scene").unwrap().as_c_str()),
+        ) {
+            let mut desc = scene.to_description(Path::new(""));
+            desc.camera = CameraDescription::from_camera(&cam);
+            desc.render.tone_mapping =
+                ToneMapperDescription::from_tone_mapper(tone_mapping.operator);
+            desc.render.denoise = denoise;
+            match desc.save("scene.toml") {
+                Ok(_) => println!("Saved scene to scene.toml"),
+                Err(err) => eprintln!("error: {err}"),
+            }
+        }
+
         res_scale = d.gui_slider_bar(
             Rectangle::new(200f32, (s_height - 20) as f32, 200f32, 20f32),
             None,
@@ -145,6 +250,17 @@ fn main() {
     }
 }
 
+/// The tone mapper the `T` key switches to after `current`.
+fn next_tone_mapper(current: ToneMapper) -> ToneMapper {
+    match current {
+        ToneMapper::Clamp => ToneMapper::Reinhard,
+        ToneMapper::Reinhard => ToneMapper::ExtendedReinhard { white: 4f32 },
+        ToneMapper::ExtendedReinhard { .. } => ToneMapper::Hable,
+        ToneMapper::Hable => ToneMapper::AcesFitted,
+        ToneMapper::AcesFitted => ToneMapper::Clamp,
+    }
+}
+
 fn handle_input(d: &RaylibDrawHandle<'_>, cam: &mut RayCamera) {
     if d.is_key_down(KeyboardKey::KEY_W) {
         cam.position += cam.direction * 0.05;
@@ -152,11 +268,12 @@ fn handle_input(d: &RaylibDrawHandle<'_>, cam: &mut RayCamera) {
     if d.is_key_down(KeyboardKey::KEY_S) {
         cam.position -= cam.direction * 0.05;
     }
+    let (_, right, _) = cam.basis();
     if d.is_key_down(KeyboardKey::KEY_A) {
-        cam.position -= Vector3::new(0.0, 1.0, 0.0).cross(cam.direction) * 0.05;
+        cam.position -= right * 0.05;
     }
     if d.is_key_down(KeyboardKey::KEY_D) {
-        cam.position += Vector3::new(0.0, 1.0, 0.0).cross(cam.direction) * 0.05;
+        cam.position += right * 0.05;
     }
 
     if d.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
@@ -177,44 +294,6 @@ fn handle_input(d: &RaylibDrawHandle<'_>, cam: &mut RayCamera) {
             cam.pitch = -89.0;
         }
 
-        cam.direction = Vector3::new(
-            cam.yaw.to_radians().cos() * cam.pitch.to_radians().cos(),
-            cam.pitch.to_radians().sin(),
-            cam.yaw.to_radians().sin() * cam.pitch.to_radians().cos(),
-        );
+        cam.update_direction();
     }
 }
-
-fn init_sphere_scene(scene: &mut Scene) {
-    let white_diffuse_mat: Arc<dyn RTMaterial> = Arc::new(LambertianMaterial::new(Vector3::new(
-        0.5f32, 0.5f32, 0.5f32,
-    )));
-    let red_diffuse_mat: Arc<dyn RTMaterial> =
-        Arc::new(LambertianMaterial::new(Vector3::new(0.65, 0.05, 0.05)));
-
-    let metal_mat = Arc::new(MetalMaterial::new(Vector3::one(), 0.1));
-
-    let bottom_plane: Box<dyn SceneObject> = Box::new(Plane::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.0, 1.0, 0.0),
-        Arc::clone(&white_diffuse_mat),
-    ));
-
-    let sphere_b: Box<dyn SceneObject> = Box::new(Sphere::new(
-        Vector3::new(0.0, 0.75, 3.0),
-        0.75,
-        Arc::clone(&red_diffuse_mat),
-    ));
-
-    let t = Matrix::translate(0f32, 3f32, 5f32);
-
-    let model = Mesh::from_obj(
-        "models/dragon_simple.obj",
-        Transform::new(Matrix::rotate_y(PI) * Matrix::scale(10f32, 10f32, 10f32) * t),
-        Arc::clone(&white_diffuse_mat),
-    );
-
-    scene.add_object(bottom_plane);
-    // scene.add_object(sphere_b);
-    scene.add_object(Box::new(model));
-}
@@ -1,5 +1,6 @@
 use raylib::math::Matrix;
 
+#[derive(Clone, Copy)]
 pub struct Transform {
     pub m: Matrix,
     pub inv: Matrix,
@@ -0,0 +1,270 @@
+use crate::rendering::{ExrSampleType, ExrWriter, Framebuffer};
+use crate::scene::HitData;
+use raylib::math::Vector3;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::collections::BTreeMap;
+
+/// Arbitrary output variables, per pixel values besides the final color that
+/// `Renderer::render_sample_aovs` can fill in the same pass. Everything but
+/// the lighting splits describes the first surface seen through the pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Aov {
+    /// Distance along the camera ray, zero where it escaped.
+    Depth,
+    /// World space hit position.
+    Position,
+    /// Shading normal facing the camera, zero where the camera ray escaped.
+    Normal,
+    /// Normal of the actual surface facing the camera.
+    GeometricNormal,
+    /// Base color of the surface, white where the camera ray escaped.
+    Albedo,
+    /// Index of the object in the scene plus one, zero where the camera ray
+    /// escaped. `ObjectMask::add_exr_layers` writes ids the same way.
+    ObjectId,
+    /// Id of the object's material plus one, zero where the camera ray
+    /// escaped.
+    MaterialId,
+    /// Surface parameterization in the first two channels.
+    Uv,
+    /// Light that reached the camera after at most one bounce, including
+    /// emitters and the environment seen directly.
+    Direct,
+    /// Everything else, so that `Direct` and `Indirect` add up to the image.
+    Indirect,
+    /// Mesh BVH nodes visited to find the first hit, see `HitData::node_hits`.
+    /// Written as raw counts, leaving the scale of a heat map to the viewer.
+    BvhNodes,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 11] = [
+        Aov::Depth,
+        Aov::Position,
+        Aov::Normal,
+        Aov::GeometricNormal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Uv,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::BvhNodes,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::GeometricNormal => "geometric_normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Uv => "uv",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::BvhNodes => "bvh_nodes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    /// Channel names used for the AOV's layer in an EXR file, taken from the
+    /// first components of the buffer.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal | Aov::GeometricNormal => &["X", "Y", "Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Uv => &["U", "V"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+            Aov::BvhNodes => &["count"],
+        }
+    }
+
+    /// Whether the AOV holds part of the image's light and is exposed along
+    /// with it.
+    pub fn is_lighting(&self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect)
+    }
+
+    /// Whether samples are averaged. Ids would turn into meaningless
+    /// fractions along edges, so they keep the value of the first sample.
+    pub fn is_filtered(&self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+/// What one camera ray contributes to every AOV.
+#[derive(Clone, Copy)]
+pub(crate) struct AovSample {
+    pub depth: f32,
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub geometric_normal: Vector3,
+    pub albedo: Vector3,
+    pub object_id: f32,
+    pub material_id: f32,
+    pub uv: Vector3,
+    pub direct: Vector3,
+    pub indirect: Vector3,
+    pub bvh_nodes: f32,
+}
+
+impl Default for AovSample {
+    /// The values for a camera ray that escapes the scene.
+    fn default() -> Self {
+        AovSample {
+            depth: 0f32,
+            position: Vector3::zero(),
+            normal: Vector3::zero(),
+            geometric_normal: Vector3::zero(),
+            albedo: Vector3::one(),
+            object_id: 0f32,
+            material_id: 0f32,
+            uv: Vector3::zero(),
+            direct: Vector3::zero(),
+            indirect: Vector3::zero(),
+            bvh_nodes: 0f32,
+        }
+    }
+}
+
+impl AovSample {
+    /// Records the surface the camera ray hit first.
+    pub fn set_hit(&mut self, hit: &HitData, depth: f32, albedo: Vector3) {
+        self.depth = depth;
+        self.position = hit.position;
+        self.normal = hit.normal;
+        self.geometric_normal = hit.geometric_normal;
+        self.albedo = albedo;
+        self.object_id = (hit.object_id + 1) as f32;
+        self.material_id = (hit.material_id + 1) as f32;
+        self.uv = Vector3::new(hit.uv.x, hit.uv.y, 0f32);
+        self.bvh_nodes = hit.node_hits as f32;
+    }
+
+    fn value(&self, aov: Aov) -> Vector3 {
+        match aov {
+            Aov::Depth => Vector3::new(self.depth, self.depth, self.depth),
+            Aov::Position => self.position,
+            Aov::Normal => self.normal,
+            Aov::GeometricNormal => self.geometric_normal,
+            Aov::Albedo => self.albedo,
+            Aov::ObjectId => Vector3::new(self.object_id, self.object_id, self.object_id),
+            Aov::MaterialId => Vector3::new(self.material_id, self.material_id, self.material_id),
+            Aov::Uv => self.uv,
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+            Aov::BvhNodes => Vector3::new(self.bvh_nodes, self.bvh_nodes, self.bvh_nodes),
+        }
+    }
+}
+
+/// The enabled AOVs of an image, each accumulated in its own buffer like the
+/// color buffer.
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    buffers: BTreeMap<Aov, Framebuffer>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> AovBuffers {
+        AovBuffers {
+            width,
+            height,
+            buffers: aovs
+                .iter()
+                .map(|&aov| (aov, Framebuffer::new(width, height)))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&Framebuffer> {
+        self.buffers.get(&aov)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Aov, &Framebuffer)> {
+        self.buffers.iter().map(|(&aov, buffer)| (aov, buffer))
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.values_mut().for_each(Framebuffer::clear);
+    }
+
+    /// Drops the buffers of AOVs `keep` returns false for.
+    pub fn retain(&mut self, mut keep: impl FnMut(Aov) -> bool) {
+        self.buffers.retain(|&aov, _| keep(aov));
+    }
+
+    /// Applies an exposure factor to the lighting AOVs, see `Framebuffer::expose`.
+    pub fn expose(&mut self, factor: Vector3) {
+        self.buffers
+            .iter_mut()
+            .filter(|(aov, _)| aov.is_lighting())
+            .for_each(|(_, buffer)| buffer.expose(factor));
+    }
+
+    /// Averages the filtered AOVs over `scale` samples.
+    pub fn normalize(&mut self, scale: f32) {
+        self.buffers
+            .iter_mut()
+            .filter(|(aov, _)| aov.is_filtered())
+            .for_each(|(_, buffer)| buffer.normalize(scale));
+    }
+
+    /// Adds one sample per pixel. `first` tells whether these are the first
+    /// samples since the buffers were cleared, the only ones ids are taken from.
+    pub(crate) fn accumulate(&mut self, samples: &[AovSample], first: bool) {
+        assert_eq!(samples.len(), self.width * self.height);
+
+        for (&aov, buffer) in self.buffers.iter_mut() {
+            if !aov.is_filtered() && !first {
+                continue;
+            }
+
+            buffer
+                .data
+                .par_iter_mut()
+                .zip(samples.par_iter())
+                .for_each(|(pixel, sample)| {
+                    if aov.is_filtered() {
+                        *pixel += sample.value(aov);
+                    } else {
+                        *pixel = sample.value(aov);
+                    }
+                });
+        }
+    }
+
+    /// Adds every AOV to `writer` as a layer named after it. Ids are always
+    /// written as full floats so large ones stay exact.
+    pub fn add_exr_layers(&self, writer: &mut ExrWriter, scale: f32, sample_type: ExrSampleType) {
+        for (&aov, buffer) in &self.buffers {
+            let (scale, sample_type) = if aov.is_filtered() {
+                (scale, sample_type)
+            } else {
+                (1f32, ExrSampleType::Float)
+            };
+
+            for (i, channel) in aov.channels().iter().enumerate() {
+                let samples = buffer
+                    .data
+                    .iter()
+                    .map(|c| [c.x, c.y, c.z][i] / scale)
+                    .collect();
+                writer.add_channel(&format!("{}.{channel}", aov.name()), samples, sample_type);
+            }
+        }
+    }
+}
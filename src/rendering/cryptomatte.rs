@@ -0,0 +1,200 @@
+use crate::rendering::{ExrSampleType, ExrWriter, MaskEntry, ObjectMask, BACKGROUND_ID};
+use crate::scene::models::Scene;
+
+/// (id, coverage) pairs written per pixel, enough for the corners where
+/// several small objects meet.
+pub const CRYPTOMATTE_RANKS: usize = 6;
+
+/// Adds `mask` to `writer` as the `CryptoObject` and `CryptoMaterial` layers
+/// of the Cryptomatte spec, so compositors can pull an anti-aliased matte for
+/// any object or material by name. Objects are named `object_<id>`, materials
+/// by the name they were registered under.
+pub fn add_cryptomattes(writer: &mut ExrWriter, mask: &ObjectMask, scene: &Scene) {
+    add_cryptomatte(
+        writer,
+        "CryptoObject",
+        mask,
+        |e| e.object_id,
+        |id| format!("object_{id}"),
+    );
+    add_cryptomatte(
+        writer,
+        "CryptoMaterial",
+        mask,
+        |e| e.material_id,
+        |id| {
+            scene
+                .material_name(id as usize)
+                .map_or_else(|| format!("material_{id}"), String::from)
+        },
+    );
+}
+
+/// Writes the `CRYPTOMATTE_RANKS` ids covering most of every pixel as
+/// `<type_name>00.RGBA`, `<type_name>01.RGBA`, ..., two (name hash, coverage)
+/// pairs per layer, and the manifest mapping names to hashes as header
+/// attributes.
+fn add_cryptomatte(
+    writer: &mut ExrWriter,
+    type_name: &str,
+    mask: &ObjectMask,
+    id: impl Fn(&MaskEntry) -> i32,
+    name: impl Fn(i32) -> String,
+) {
+    let pixel_count = mask.width * mask.height;
+    let mut ranks = vec![vec![(0f32, 0f32); pixel_count]; CRYPTOMATTE_RANKS];
+    for i in 0..pixel_count {
+        let ranked = mask.ranked(i, &id);
+        let objects = ranked.iter().filter(|(id, _)| *id != BACKGROUND_ID);
+        for (pairs, (id, coverage)) in ranks.iter_mut().zip(objects) {
+            pairs[i] = (name_hash(&name(*id)), *coverage);
+        }
+    }
+
+    for (rank, pairs) in ranks.into_iter().enumerate() {
+        let layer = format!("{type_name}{:02}", rank / 2);
+        let (id_channel, coverage_channel) = if rank % 2 == 0 {
+            ("R", "G")
+        } else {
+            ("B", "A")
+        };
+        let (ids, coverages) = pairs.into_iter().unzip();
+        // hashes are bit patterns, a half float would scramble them
+        writer.add_channel(&format!("{layer}.{id_channel}"), ids, ExrSampleType::Float);
+        writer.add_channel(
+            &format!("{layer}.{coverage_channel}"),
+            coverages,
+            ExrSampleType::Float,
+        );
+    }
+
+    let mut names: Vec<String> = mask.ids(&id).into_iter().map(&name).collect();
+    names.sort();
+    let manifest = names
+        .iter()
+        .map(|name| {
+            format!(
+                "{}:\"{:08x}\"",
+                json_string(name),
+                name_hash(name).to_bits()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let key = metadata_key(type_name);
+    writer.add_attribute(&format!("cryptomatte/{key}/name"), type_name);
+    writer.add_attribute(&format!("cryptomatte/{key}/hash"), "MurmurHash3_32");
+    writer.add_attribute(
+        &format!("cryptomatte/{key}/conversion"),
+        "uint32_to_float32",
+    );
+    writer.add_attribute(
+        &format!("cryptomatte/{key}/manifest"),
+        &format!("{{{manifest}}}"),
+    );
+}
+
+/// Tells apart the header attributes of different Cryptomatte layers: the
+/// first seven hex digits of the hash of the layer's name.
+fn metadata_key(type_name: &str) -> String {
+    format!("{:08x}", murmur3_32(type_name.as_bytes(), 0))[..7].to_string()
+}
+
+/// The float Cryptomatte stores for `name`: its MurmurHash3 with the bits
+/// reinterpreted, nudged away from denormals, infinities and NaNs.
+pub fn name_hash(name: &str) -> f32 {
+    let mut hash = murmur3_32(name.as_bytes(), 0);
+    let exponent = (hash >> 23) & 0xff;
+    if exponent == 0 || exponent == 0xff {
+        hash ^= 1 << 23;
+    }
+    f32::from_bits(hash)
+}
+
+/// MurmurHash3, the x86 32 bit variant.
+fn murmur3_32(bytes: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = seed;
+    let mut blocks = bytes.chunks_exact(4);
+    for block in &mut blocks {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        h ^= mix(k);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, &byte| (k << 8) | byte as u32);
+        h ^= mix(k);
+    }
+
+    h ^= bytes.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+/// `text` as a JSON string literal in plain ASCII, which EXR text attributes
+/// can hold.
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            ' '..='~' => json.push(c),
+            _ => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    json.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_matches_reference_values() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e28b7);
+        assert_eq!(murmur3_32(b"hello", 0), 0x248bfa47);
+        assert_eq!(murmur3_32(b"abc", 0), 0xb3dd93fa);
+        assert_eq!(murmur3_32(b"\0\0\0\0", 0), 0x2362f9de);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0x9747b28c),
+            0x2fa826cd
+        );
+    }
+
+    #[test]
+    fn name_hash_matches_spec() {
+        // the manifest example of the Cryptomatte specification
+        assert_eq!(name_hash("bunny").to_bits(), 0x13851a76);
+        assert_eq!(name_hash("default").to_bits(), 0x42c9679f);
+        // hashes to 0xffc16c53, a NaN, so the lowest exponent bit is flipped
+        assert_eq!(name_hash("object_440").to_bits(), 0xff416c53);
+        assert!(name_hash("object_440").is_finite());
+    }
+
+    #[test]
+    fn metadata_key_is_seven_hex_digits_of_the_layer_hash() {
+        assert_eq!(metadata_key("CryptoObject"), "3ae39a5");
+        assert_eq!(metadata_key("CryptoMaterial"), "be359d6");
+    }
+}
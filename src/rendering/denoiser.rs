@@ -0,0 +1,253 @@
+use crate::rendering::{Aov, AovBuffers, Framebuffer};
+use crate::utils::luminance;
+use raylib::math::Vector3;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Edge-avoiding à-trous wavelet filter in the spirit of SVGF. Lighting is
+/// separated from the albedo so textures stay sharp, then blurred over a
+/// growing 5x5 footprint while normals, depth and the local noise level keep
+/// it from bleeding across edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    /// Number of filter passes. Each doubles the footprint, five cover a
+    /// 125 pixel wide area.
+    pub iterations: u32,
+    /// How many standard deviations of the estimated noise two luminance
+    /// values may differ by and still be averaged.
+    pub sigma_luminance: f32,
+    /// Exponent on the cosine between two normals, higher is stricter.
+    pub sigma_normal: f32,
+    /// Relative depth difference per pixel of distance that is tolerated.
+    pub sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_luminance: 4f32,
+            sigma_normal: 128f32,
+            sigma_depth: 0.02f32,
+        }
+    }
+}
+
+/// Weights of the B3 spline the à-trous passes spread apart.
+const KERNEL: [f32; 5] = [
+    1f32 / 16f32,
+    1f32 / 4f32,
+    3f32 / 8f32,
+    1f32 / 4f32,
+    1f32 / 16f32,
+];
+/// Smallest albedo lighting is divided by, so black surfaces don't blow up.
+const MIN_ALBEDO: f32 = 1e-3f32;
+
+/// Features of one pixel, normalized.
+struct Guide {
+    normal: Vector3,
+    albedo: Vector3,
+    depth: f32,
+}
+
+/// The image between two filter passes.
+struct Level<'a> {
+    width: usize,
+    height: usize,
+    step: usize,
+    lighting: &'a [Vector3],
+    variance: &'a [f32],
+    /// `variance` after a 3x3 blur, used to scale the luminance weights.
+    blurred_variance: &'a [f32],
+    guides: &'a [Guide],
+}
+
+impl Denoiser {
+    /// The AOVs `denoise` is guided by.
+    pub const AOVS: [Aov; 3] = [Aov::Normal, Aov::Albedo, Aov::Depth];
+
+    /// Denoises `color` guided by `aovs`, which must include `Denoiser::AOVS`.
+    /// Both hold the sum of `scale` samples; the averaged, cleaned image is
+    /// returned.
+    pub fn denoise(&self, color: &Framebuffer, scale: f32, aovs: &AovBuffers) -> Framebuffer {
+        let (width, height) = (color.width, color.height);
+        let [normals, albedos, depths] = Denoiser::AOVS.map(|aov| {
+            aovs.get(aov)
+                .unwrap_or_else(|| panic!("denoising needs the {} AOV", aov.name()))
+        });
+
+        let guides: Vec<Guide> = (0..width * height)
+            .map(|i| {
+                let albedo = albedos.data[i] / scale;
+                let normal = normals.data[i];
+                Guide {
+                    normal: if normal.length() > 0f32 {
+                        normal.normalized()
+                    } else {
+                        normal
+                    },
+                    albedo: Vector3::new(
+                        albedo.x.max(MIN_ALBEDO),
+                        albedo.y.max(MIN_ALBEDO),
+                        albedo.z.max(MIN_ALBEDO),
+                    ),
+                    depth: depths.data[i].x / scale,
+                }
+            })
+            .collect();
+
+        let mut lighting: Vec<Vector3> = color
+            .data
+            .iter()
+            .zip(&guides)
+            .map(|(c, guide)| {
+                let c = *c / scale;
+                Vector3::new(
+                    c.x / guide.albedo.x,
+                    c.y / guide.albedo.y,
+                    c.z / guide.albedo.z,
+                )
+            })
+            .collect();
+        let mut variance = spatial_variance(&lighting, width, height);
+
+        for iteration in 0..self.iterations {
+            let level = Level {
+                width,
+                height,
+                step: 1usize << iteration,
+                lighting: &lighting,
+                variance: &variance,
+                blurred_variance: &blur_3x3(&variance, width, height),
+                guides: &guides,
+            };
+
+            let filtered: Vec<(Vector3, f32)> = (0..width * height)
+                .into_par_iter()
+                .map(|i| self.filter_pixel(&level, i))
+                .collect();
+
+            (lighting, variance) = filtered.into_iter().unzip();
+        }
+
+        let mut output = Framebuffer::new(width, height);
+        output.data = lighting
+            .iter()
+            .zip(&guides)
+            .map(|(l, guide)| *l * guide.albedo)
+            .collect();
+        output
+    }
+
+    /// One à-trous tap around pixel `i`, returning its filtered lighting and
+    /// the variance of that estimate.
+    fn filter_pixel(&self, level: &Level, i: usize) -> (Vector3, f32) {
+        let Level {
+            width,
+            height,
+            step,
+            lighting,
+            variance,
+            guides,
+            ..
+        } = *level;
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let center = &guides[i];
+        let center_lum = luminance(lighting[i]);
+        let lum_scale = self.sigma_luminance * level.blurred_variance[i].max(0f32).sqrt() + 1e-4f32;
+
+        let mut sum = Vector3::zero();
+        let mut sum_variance = 0f32;
+        let mut sum_weight = 0f32;
+
+        for (ky, wy) in KERNEL.iter().enumerate() {
+            for (kx, wx) in KERNEL.iter().enumerate() {
+                let dx = (kx as isize - 2) * step as isize;
+                let dy = (ky as isize - 2) * step as isize;
+                let (sx, sy) = (x + dx, y + dy);
+                if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+                    continue;
+                }
+
+                let j = sy as usize * width + sx as usize;
+                let other = &guides[j];
+
+                let w_normal =
+                    if center.normal == Vector3::zero() && other.normal == Vector3::zero() {
+                        1f32
+                    } else {
+                        center
+                            .normal
+                            .dot(other.normal)
+                            .max(0f32)
+                            .powf(self.sigma_normal)
+                    };
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                let w_depth = (-(center.depth - other.depth).abs()
+                    / (self.sigma_depth * center.depth * distance + 1e-4f32))
+                    .exp();
+                let w_lum = (-(center_lum - luminance(lighting[j])).abs() / lum_scale).exp();
+
+                let weight = wx * wy * w_normal * w_depth * w_lum;
+                sum += lighting[j] * weight;
+                sum_variance += weight * weight * variance[j];
+                sum_weight += weight;
+            }
+        }
+
+        if sum_weight <= 0f32 {
+            return (lighting[i], variance[i]);
+        }
+        (sum / sum_weight, sum_variance / (sum_weight * sum_weight))
+    }
+}
+
+/// Luminance variance in the 5x5 neighbourhood of every pixel, standing in
+/// for the noise level of a single image.
+fn spatial_variance(lighting: &[Vector3], width: usize, height: usize) -> Vec<f32> {
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let (mut sum, mut sum_sq, mut n) = (0f32, 0f32, 0f32);
+
+            for sy in y.saturating_sub(2)..(y + 3).min(height) {
+                for sx in x.saturating_sub(2)..(x + 3).min(width) {
+                    let l = luminance(lighting[sy * width + sx]);
+                    sum += l;
+                    sum_sq += l * l;
+                    n += 1f32;
+                }
+            }
+
+            let mean = sum / n;
+            (sum_sq / n - mean * mean).max(0f32)
+        })
+        .collect()
+}
+
+/// 3x3 Gaussian blur, steadying the variance the luminance weights use.
+fn blur_3x3(values: &[f32], width: usize, height: usize) -> Vec<f32> {
+    const WEIGHTS: [f32; 3] = [0.25f32, 0.5f32, 0.25f32];
+
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let (mut sum, mut sum_weight) = (0f32, 0f32);
+
+            for (ky, wy) in WEIGHTS.iter().enumerate() {
+                for (kx, wx) in WEIGHTS.iter().enumerate() {
+                    let (sx, sy) = ((x + kx).wrapping_sub(1), (y + ky).wrapping_sub(1));
+                    if sx >= width || sy >= height {
+                        continue;
+                    }
+                    sum += values[sy * width + sx] * wx * wy;
+                    sum_weight += wx * wy;
+                }
+            }
+
+            sum / sum_weight
+        })
+        .collect()
+}
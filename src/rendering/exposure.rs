@@ -0,0 +1,84 @@
+use crate::utils::{luminance, xyy_to_rgb};
+use raylib::math::Vector3;
+
+/// Photographic exposure settings. They are calibrated so that one second at
+/// f/1 and ISO 100 leaves the rendered radiance unchanged; every stop less
+/// light halves the image brightness like it would on a real camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    /// Shutter time in seconds.
+    pub shutter: f32,
+    pub f_number: f32,
+    pub iso: f32,
+    /// Exposure compensation in stops.
+    pub compensation: f32,
+    /// Color temperature in kelvin that comes out white. Lower values make the
+    /// image cooler, correcting for warm light such as tungsten (~3200 K).
+    pub white_balance: f32,
+}
+
+/// White balance that leaves colors unchanged.
+pub const NEUTRAL_WHITE_BALANCE: f32 = 6500f32;
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure {
+            shutter: 1f32,
+            f_number: 1f32,
+            iso: 100f32,
+            compensation: 0f32,
+            white_balance: NEUTRAL_WHITE_BALANCE,
+        }
+    }
+}
+
+impl Exposure {
+    /// Exposure value at ISO 100, `log2(N² / t)` adjusted for the ISO.
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter * 100f32 / self.iso).log2()
+    }
+
+    /// Brightness multiplier from shutter, f-number, ISO and compensation.
+    pub fn scale(&self) -> f32 {
+        (self.compensation - self.ev100()).exp2()
+    }
+
+    /// Per channel multiplier to apply to linear radiance, including white balance.
+    pub fn factor(&self) -> Vector3 {
+        white_balance_gains(self.white_balance) * self.scale()
+    }
+}
+
+/// Channel gains that turn light of color temperature `kelvin` neutral,
+/// normalized so gray surfaces keep their luminance.
+pub fn white_balance_gains(kelvin: f32) -> Vector3 {
+    let white = planckian_rgb(NEUTRAL_WHITE_BALANCE);
+    let light = planckian_rgb(kelvin);
+    let gains = Vector3::new(white.x / light.x, white.y / light.y, white.z / light.z);
+
+    gains / luminance(gains)
+}
+
+/// Linear sRGB color of a black body at `kelvin`, from the Planckian locus
+/// approximation of Kim et al.
+fn planckian_rgb(kelvin: f32) -> Vector3 {
+    let t = kelvin.clamp(1667f32, 25000f32);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000f32 {
+        -0.2661239e9f32 / t3 - 0.2343589e6f32 / t2 + 0.8776956e3f32 / t + 0.179910f32
+    } else {
+        -3.0258469e9f32 / t3 + 2.107038e6f32 / t2 + 0.2226347e3f32 / t + 0.240390f32
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222f32 {
+        -1.1063814f32 * x3 - 1.3481102f32 * x2 + 2.1855583f32 * x - 0.20219683f32
+    } else if t <= 4000f32 {
+        -0.9549476f32 * x3 - 1.3741859f32 * x2 + 2.09137f32 * x - 0.16748867f32
+    } else {
+        3.081758f32 * x3 - 5.873387f32 * x2 + 3.7511299f32 * x - 0.37001483f32
+    };
+
+    let rgb = xyy_to_rgb(x, y, 1f32);
+    Vector3::new(rgb.x.max(1e-4f32), rgb.y.max(1e-4f32), rgb.z.max(1e-4f32))
+}
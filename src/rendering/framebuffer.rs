@@ -1,4 +1,11 @@
+use crate::rendering::{rgbe, srgb_eotf, AovBuffers, ExrSampleType, ExrWriter, ToneMapping};
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::error::ImageFormatHint;
+use image::{ImageBuffer, ImageError, ImageResult, Rgb, RgbImage};
 use raylib::math::Vector3;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
 
 pub struct Framebuffer {
     pub data: Vec<Vector3>,
@@ -41,18 +48,258 @@ impl Framebuffer {
         return self.to_bytes_s(1f32);
     }
 
+    /// Multiplies every pixel by a per channel exposure factor, see `Exposure::factor`.
+    pub fn expose(&mut self, factor: Vector3) {
+        self.data.iter_mut().for_each(|v| *v *= factor);
+    }
+
     pub fn to_bytes_s(&self, scale: f32) -> Vec<u8> {
+        self.to_bytes_mapped(scale, Vector3::one(), &ToneMapping::default())
+    }
+
+    /// RGBA bytes for display: every pixel divided by `scale`, multiplied by
+    /// `exposure` and put through `tone_mapping`. The accumulated data is left
+    /// alone.
+    pub fn to_bytes_mapped(
+        &self,
+        scale: f32,
+        exposure: Vector3,
+        tone_mapping: &ToneMapping,
+    ) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![0; self.width * self.height * 4];
 
-        let mut i = 0;
-        for color in &self.data {
-            bytes[i] = ((color.x / scale).sqrt() * 255f32) as u8;
-            bytes[i + 1] = ((color.y / scale).sqrt() * 255f32) as u8;
-            bytes[i + 2] = ((color.z / scale).sqrt() * 255f32) as u8;
-            bytes[i + 3] = 255;
-            i += 4;
+        for (i, color) in self.data.iter().enumerate() {
+            let [r, g, b] = tone_mapping.encode(
+                *color * exposure / scale,
+                i % self.width,
+                i / self.width,
+                255f32,
+            );
+            bytes[i * 4] = r as u8;
+            bytes[i * 4 + 1] = g as u8;
+            bytes[i * 4 + 2] = b as u8;
+            bytes[i * 4 + 3] = 255;
         }
 
         return bytes;
     }
+
+    pub fn to_bytes16_s(&self, scale: f32) -> Vec<u16> {
+        self.to_bytes16_mapped(scale, Vector3::one(), &ToneMapping::default())
+    }
+
+    /// RGB values for 16 bit images, mapped like `to_bytes_mapped`.
+    pub fn to_bytes16_mapped(
+        &self,
+        scale: f32,
+        exposure: Vector3,
+        tone_mapping: &ToneMapping,
+    ) -> Vec<u16> {
+        self.data
+            .iter()
+            .enumerate()
+            .flat_map(|(i, color)| {
+                tone_mapping
+                    .encode(
+                        *color * exposure / scale,
+                        i % self.width,
+                        i / self.width,
+                        65535f32,
+                    )
+                    .map(|c| c as u16)
+            })
+            .collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        self.save_s(path, 1f32)
+    }
+
+    /// Saves the framebuffer, picking the encoder from the file extension.
+    /// 8 and 16 bit images are clamped, see `save_as` for other tone mappers.
+    pub fn save_s<P: AsRef<Path>>(&self, path: P, scale: f32) -> ImageResult<()> {
+        let format = ImageFormat::from_path(&path).ok_or_else(|| {
+            let ext = path.as_ref().extension().unwrap_or_default();
+            ImageError::Unsupported(ImageFormatHint::PathExtension(ext.into()).into())
+        })?;
+
+        self.save_as(path, format, scale, Vector3::one(), &ToneMapping::default())
+    }
+
+    /// Saves the framebuffer as `format`, every pixel divided by `scale` and
+    /// multiplied by `exposure`. 8 and 16 bit formats then go through
+    /// `tone_mapping`, `hdr` and `exr` keep the linear radiance.
+    pub fn save_as<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: ImageFormat,
+        scale: f32,
+        exposure: Vector3,
+        tone_mapping: &ToneMapping,
+    ) -> ImageResult<()> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let exposed;
+        let linear = if exposure == Vector3::one() {
+            self
+        } else {
+            exposed = Framebuffer {
+                data: self.data.iter().map(|v| *v * exposure).collect(),
+                width: self.width,
+                height: self.height,
+            };
+            &exposed
+        };
+
+        match format {
+            ImageFormat::Exr | ImageFormat::ExrHalf => {
+                let sample_type = if format == ImageFormat::Exr {
+                    ExrSampleType::Float
+                } else {
+                    ExrSampleType::Half
+                };
+                linear
+                    .save_exr(path, sample_type, scale)
+                    .map_err(|err| ImageError::IoError(io::Error::other(err)))
+            }
+            ImageFormat::Hdr => Ok(rgbe::write_hdr(path, linear, scale)?),
+            ImageFormat::Png16 | ImageFormat::Tiff16 => {
+                let img = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_raw(
+                    width,
+                    height,
+                    self.to_bytes16_mapped(scale, exposure, tone_mapping),
+                )
+                .expect("framebuffer size mismatch");
+                let codec = if format == ImageFormat::Png16 {
+                    image::ImageFormat::Png
+                } else {
+                    image::ImageFormat::Tiff
+                };
+                img.save_with_format(path, codec)
+            }
+            _ => {
+                let rgb: Vec<u8> = self
+                    .to_bytes_mapped(scale, exposure, tone_mapping)
+                    .chunks_exact(4)
+                    .flat_map(|px| [px[0], px[1], px[2]])
+                    .collect();
+                let img =
+                    RgbImage::from_raw(width, height, rgb).expect("framebuffer size mismatch");
+
+                match format {
+                    ImageFormat::Ppm => {
+                        let out = BufWriter::new(File::create(path)?);
+                        img.write_with_encoder(
+                            PnmEncoder::new(out)
+                                .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+                        )
+                    }
+                    ImageFormat::Png => img.save_with_format(path, image::ImageFormat::Png),
+                    ImageFormat::Jpeg => img.save_with_format(path, image::ImageFormat::Jpeg),
+                    ImageFormat::Bmp => img.save_with_format(path, image::ImageFormat::Bmp),
+                    ImageFormat::Tga => img.save_with_format(path, image::ImageFormat::Tga),
+                    _ => img.save_with_format(path, image::ImageFormat::Tiff),
+                }
+            }
+        }
+    }
+
+    /// Writes linear radiance without any gamma or clamping.
+    pub fn save_exr<P: AsRef<Path>>(
+        &self,
+        path: P,
+        sample_type: ExrSampleType,
+        scale: f32,
+    ) -> exr::error::Result<()> {
+        let mut writer = ExrWriter::new(self.width, self.height);
+        writer.add_layer("", self, scale, sample_type);
+        writer.write(path)
+    }
+
+    /// Like `save_exr`, with every AOV in its own layer of the same file.
+    pub fn save_exr_aovs<P: AsRef<Path>>(
+        &self,
+        path: P,
+        sample_type: ExrSampleType,
+        scale: f32,
+        aovs: &AovBuffers,
+    ) -> exr::error::Result<()> {
+        let mut writer = ExrWriter::new(self.width, self.height);
+        writer.add_layer("", self, scale, sample_type);
+        aovs.add_exr_layers(&mut writer, scale, sample_type);
+        writer.write(path)
+    }
+
+    /// Loads an image saved by `save`, decoding the sRGB curve so the data is
+    /// linear again. Values the tone mapper clamped stay clamped. EXR and HDR
+    /// files already hold linear data and are read as is.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Framebuffer> {
+        match ImageFormat::from_path(&path) {
+            Some(ImageFormat::Hdr) => return Ok(rgbe::read_hdr(path)?),
+            Some(ImageFormat::Exr) => {
+                return Framebuffer::load_exr(path)
+                    .map_err(|err| ImageError::IoError(io::Error::other(err)))
+            }
+            _ => {}
+        }
+
+        let img = image::open(path)?.into_rgb32f();
+        let mut framebuf = Framebuffer::new(img.width() as usize, img.height() as usize);
+
+        framebuf
+            .data
+            .iter_mut()
+            .zip(img.pixels())
+            .for_each(|(v, px)| {
+                *v = Vector3::new(srgb_eotf(px[0]), srgb_eotf(px[1]), srgb_eotf(px[2]))
+            });
+
+        Ok(framebuf)
+    }
+
+    fn load_exr<P: AsRef<Path>>(path: P) -> exr::error::Result<Framebuffer> {
+        let img = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Framebuffer::new(resolution.width(), resolution.height()),
+            |framebuf, pos, (r, g, b, _): (f32, f32, f32, f32)| {
+                framebuf.set_pixel(pos.x(), pos.y(), Vector3::new(r, g, b))
+            },
+        )?;
+
+        Ok(img.layer_data.channel_data.pixels)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Png16,
+    Jpeg,
+    Bmp,
+    Tga,
+    Tiff,
+    Tiff16,
+    Exr,
+    ExrHalf,
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Guesses the format from the file extension. 16 bit and half float formats
+    /// are never picked here, they need to be requested through `Framebuffer::save_as`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+
+        match ext.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "bmp" => Some(ImageFormat::Bmp),
+            "tga" => Some(ImageFormat::Tga),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
 }
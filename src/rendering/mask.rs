@@ -0,0 +1,236 @@
+use crate::rendering::{ExrSampleType, ExrWriter, Framebuffer};
+use raylib::math::Vector3;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use std::collections::BTreeSet;
+
+/// Id stored for samples that didn't hit anything.
+pub const BACKGROUND_ID: i32 = -1;
+
+/// An object seen through a pixel and the number of samples that hit it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaskEntry {
+    pub object_id: i32,
+    pub material_id: i32,
+    pub samples: u32,
+}
+
+/// Which objects and materials are seen through every pixel. Samples are
+/// jittered over the pixel like the image's, so edges keep their partial
+/// coverage and mattes come out anti-aliased.
+pub struct ObjectMask {
+    pub width: usize,
+    pub height: usize,
+    pub num_samples: u32,
+    /// Everything seen through each pixel, the background as `BACKGROUND_ID`,
+    /// most samples first.
+    pixels: Vec<Vec<MaskEntry>>,
+}
+
+impl ObjectMask {
+    pub fn new(width: usize, height: usize) -> ObjectMask {
+        ObjectMask {
+            width,
+            height,
+            num_samples: 0,
+            pixels: vec![Vec::new(); width * height],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.num_samples = 0;
+        self.pixels.iter_mut().for_each(Vec::clear);
+    }
+
+    /// Adds one sample per pixel, the (object id, material id) it hit.
+    pub(crate) fn accumulate(&mut self, ids: &[(i32, i32)]) {
+        assert_eq!(ids.len(), self.width * self.height);
+
+        self.pixels.par_iter_mut().zip(ids.par_iter()).for_each(
+            |(entries, &(object_id, material_id))| {
+                match entries.iter().position(|e| e.object_id == object_id) {
+                    Some(i) => {
+                        entries[i].samples += 1;
+                        // keep the order by bubbling the entry up past smaller ones
+                        let mut i = i;
+                        while i > 0 && entries[i - 1].samples < entries[i].samples {
+                            entries.swap(i - 1, i);
+                            i -= 1;
+                        }
+                    }
+                    None => entries.push(MaskEntry {
+                        object_id,
+                        material_id,
+                        samples: 1,
+                    }),
+                }
+            },
+        );
+
+        self.num_samples += 1;
+    }
+
+    /// Everything seen through pixel (`x`, `y`), most samples first.
+    pub fn entries(&self, x: usize, y: usize) -> &[MaskEntry] {
+        &self.pixels[x + y * self.width]
+    }
+
+    /// Fraction of the pixel's samples an entry covers.
+    pub fn coverage(&self, entry: &MaskEntry) -> f32 {
+        entry.samples as f32 / self.num_samples.max(1) as f32
+    }
+
+    /// The object covering most of every pixel, `BACKGROUND_ID` where that is
+    /// the background.
+    pub fn object_ids(&self) -> Vec<i32> {
+        self.pixels
+            .iter()
+            .map(|entries| entries.first().map_or(BACKGROUND_ID, |e| e.object_id))
+            .collect()
+    }
+
+    /// The material covering most of every pixel, adding up the objects that
+    /// share it.
+    pub fn material_ids(&self) -> Vec<i32> {
+        (0..self.pixels.len())
+            .map(|i| {
+                self.ranked(i, |e| e.material_id)
+                    .first()
+                    .map_or(BACKGROUND_ID, |&(id, _)| id)
+            })
+            .collect()
+    }
+
+    /// The ids `id` picks out of pixel `i`'s entries with the coverage of
+    /// each, largest first and ties by id. Entries with the same id add up.
+    pub(crate) fn ranked(&self, i: usize, id: impl Fn(&MaskEntry) -> i32) -> Vec<(i32, f32)> {
+        let mut ranked: Vec<(i32, f32)> = Vec::new();
+        for entry in &self.pixels[i] {
+            let coverage = self.coverage(entry);
+            match ranked.iter_mut().find(|(other, _)| *other == id(entry)) {
+                Some((_, sum)) => *sum += coverage,
+                None => ranked.push((id(entry), coverage)),
+            }
+        }
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+    }
+
+    /// Distinct object ids in the mask, without the background.
+    pub fn objects(&self) -> BTreeSet<i32> {
+        self.ids(|e| e.object_id)
+    }
+
+    /// Distinct material ids in the mask, without the background.
+    pub fn materials(&self) -> BTreeSet<i32> {
+        self.ids(|e| e.material_id)
+    }
+
+    /// Coverage of `object` in every pixel, for compositing it on its own.
+    pub fn object_matte(&self, object: i32) -> Vec<f32> {
+        self.matte(|e| e.object_id == object)
+    }
+
+    /// Coverage of everything with `material` in every pixel.
+    pub fn material_matte(&self, material: i32) -> Vec<f32> {
+        self.matte(|e| e.material_id == material)
+    }
+
+    /// Objects in false colors, blended by coverage along edges.
+    pub fn object_preview(&self) -> Framebuffer {
+        self.preview(|e| e.object_id)
+    }
+
+    /// Materials in false colors, blended by coverage along edges.
+    pub fn material_preview(&self) -> Framebuffer {
+        self.preview(|e| e.material_id)
+    }
+
+    /// Adds the mask to `writer`: `object_id.id` and `material_id.id` hold the
+    /// ids from `object_ids` and `material_ids` plus one as integers, so the
+    /// background is zero like in the id AOVs, and `object_<id>.A` and
+    /// `material_<id>.A` hold a matte for every id in view, counted the same
+    /// way.
+    pub fn add_exr_layers(&self, writer: &mut ExrWriter, sample_type: ExrSampleType) {
+        let as_uint = |ids: Vec<i32>| ids.into_iter().map(|id| (id + 1) as u32).collect();
+        writer.add_uint_channel("object_id.id", as_uint(self.object_ids()));
+        writer.add_uint_channel("material_id.id", as_uint(self.material_ids()));
+
+        for object in self.objects() {
+            writer.add_channel(
+                &format!("object_{}.A", object + 1),
+                self.object_matte(object),
+                sample_type,
+            );
+        }
+        for material in self.materials() {
+            writer.add_channel(
+                &format!("material_{}.A", material + 1),
+                self.material_matte(material),
+                sample_type,
+            );
+        }
+    }
+
+    /// Distinct ids `id` picks out of the mask, without the background.
+    pub(crate) fn ids(&self, id: impl Fn(&MaskEntry) -> i32) -> BTreeSet<i32> {
+        self.pixels
+            .iter()
+            .flatten()
+            .map(id)
+            .filter(|&id| id != BACKGROUND_ID)
+            .collect()
+    }
+
+    fn matte(&self, selected: impl Fn(&MaskEntry) -> bool) -> Vec<f32> {
+        self.pixels
+            .iter()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|e| selected(e))
+                    .map(|e| self.coverage(e))
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn preview(&self, id: impl Fn(&MaskEntry) -> i32) -> Framebuffer {
+        let mut preview = Framebuffer::new(self.width, self.height);
+        preview.data = self
+            .pixels
+            .iter()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|e| id_color(id(e)) * self.coverage(e))
+                    .fold(Vector3::zero(), |sum, c| sum + c)
+            })
+            .collect();
+        preview
+    }
+}
+
+/// A saturated color that is the same for the same id every time, black for
+/// the background.
+pub fn id_color(id: i32) -> Vector3 {
+    if id == BACKGROUND_ID {
+        return Vector3::zero();
+    }
+
+    // golden ratio steps around the hue circle keep neighbouring ids apart
+    let hue = (id as f32 * 0.618034f32).fract() * 6f32;
+    let x = 1f32 - (hue % 2f32 - 1f32).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1f32, x, 0f32),
+        1 => (x, 1f32, 0f32),
+        2 => (0f32, 1f32, x),
+        3 => (0f32, x, 1f32),
+        4 => (x, 0f32, 1f32),
+        _ => (1f32, 0f32, x),
+    };
+    // varying the brightness too keeps the many ids of a big scene apart
+    let value = [1f32, 0.6f32, 0.35f32][(id % 3) as usize];
+    Vector3::new(r, g, b) * value
+}
@@ -1,17 +1,49 @@
-use crate::rendering;
-use crate::rendering::renderer::EPSILON;
-use crate::utils::{rand_in_hemisphere, rand_unit_vec, reflect};
+use crate::scene::description::{arr3, MaterialDescription};
+use crate::scene::HitData;
+use crate::utils::{onb, rand_cosine_hemisphere, rand_f32, reflect};
 use raylib::math::Vector3;
+use std::f32::consts::PI;
 
+/// A direction picked by `RTMaterial::sample`.
+pub struct BSDFSample {
+    /// Incoming light direction, pointing away from the surface.
+    pub wi: Vector3,
+    /// BSDF value for `wi`, not including the cosine term.
+    pub f: Vector3,
+    /// Solid angle density `wi` was sampled with.
+    pub pdf: f32,
+    /// Set for perfectly specular lobes. Their `f` and `pdf` are only
+    /// meaningful as the ratio `f * |cos| / pdf`; `eval` and `pdf` return zero
+    /// for them since no other strategy can hit the same direction.
+    pub delta: bool,
+}
+
+/// Surface scattering as a BSDF. Directions are unit vectors pointing away
+/// from the surface: `wo` towards the viewer, `wi` towards the light.
+/// `hit.normal` faces the side `wo` arrived from and `hit.front_face` tells
+/// whether that is the outside of the surface.
 pub trait RTMaterial: Send + Sync {
-    fn attenuation(&self, position: Vector3, normal: Vector3) -> Vector3;
-    fn scatter(
-        &self,
-        in_ray: rendering::Ray,
-        position: Vector3,
-        normal: Vector3,
-    ) -> Option<rendering::Ray>;
-    fn emissive(&self, position: Vector3, normal: Vector3) -> bool;
+    fn sample(&self, wo: Vector3, hit: &HitData) -> Option<BSDFSample>;
+    fn eval(&self, wo: Vector3, wi: Vector3, hit: &HitData) -> Vector3;
+    fn pdf(&self, wo: Vector3, wi: Vector3, hit: &HitData) -> f32;
+    fn emitted(&self, _wo: Vector3, _hit: &HitData) -> Vector3 {
+        Vector3::zero()
+    }
+    /// Whether `emitted` can be non-zero. Objects with emissive materials are
+    /// sampled as lights by the renderer.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Whether every lobe is a delta lobe. `eval` and `pdf` are then always
+    /// zero, so the renderer doesn't sample lights for the surface.
+    fn is_delta(&self) -> bool {
+        false
+    }
+    /// Base color of the surface, used as the albedo feature by the denoiser.
+    fn albedo(&self, _hit: &HitData) -> Vector3 {
+        Vector3::one()
+    }
+    fn to_description(&self) -> MaterialDescription;
 }
 
 pub struct LambertianMaterial {
@@ -25,24 +57,36 @@ impl LambertianMaterial {
 }
 
 impl RTMaterial for LambertianMaterial {
-    fn attenuation(&self, _: Vector3, _: Vector3) -> Vector3 {
-        self.albedo
+    fn sample(&self, wo: Vector3, hit: &HitData) -> Option<BSDFSample> {
+        let wi = rand_cosine_hemisphere(hit.normal);
+
+        Some(BSDFSample {
+            wi,
+            f: self.eval(wo, wi, hit),
+            pdf: self.pdf(wo, wi, hit),
+            delta: false,
+        })
     }
 
-    fn scatter(
-        &self,
-        _: rendering::Ray,
-        position: Vector3,
-        normal: Vector3,
-    ) -> Option<rendering::Ray> {
-        Some(rendering::Ray::new(
-            position,
-            normal + rand_in_hemisphere(normal),
-        ))
+    fn eval(&self, _wo: Vector3, wi: Vector3, hit: &HitData) -> Vector3 {
+        if wi.dot(hit.normal) <= 0f32 {
+            return Vector3::zero();
+        }
+        self.albedo / PI
     }
 
-    fn emissive(&self, _: Vector3, _: Vector3) -> bool {
-        false
+    fn pdf(&self, _wo: Vector3, wi: Vector3, hit: &HitData) -> f32 {
+        wi.dot(hit.normal).max(0f32) / PI
+    }
+
+    fn albedo(&self, _hit: &HitData) -> Vector3 {
+        self.albedo
+    }
+
+    fn to_description(&self) -> MaterialDescription {
+        MaterialDescription::Lambertian {
+            albedo: arr3(self.albedo),
+        }
     }
 }
 
@@ -57,48 +101,242 @@ impl EmissiveMaterial {
 }
 
 impl RTMaterial for EmissiveMaterial {
-    fn attenuation(&self, _: Vector3, _: Vector3) -> Vector3 {
+    fn sample(&self, _wo: Vector3, _hit: &HitData) -> Option<BSDFSample> {
+        None
+    }
+
+    fn eval(&self, _wo: Vector3, _wi: Vector3, _hit: &HitData) -> Vector3 {
+        Vector3::zero()
+    }
+
+    fn pdf(&self, _wo: Vector3, _wi: Vector3, _hit: &HitData) -> f32 {
+        0f32
+    }
+
+    fn emitted(&self, _wo: Vector3, _hit: &HitData) -> Vector3 {
         self.emit
     }
 
-    fn scatter(&self, _: rendering::Ray, _: Vector3, _: Vector3) -> Option<rendering::Ray> {
-        None
+    fn is_emissive(&self) -> bool {
+        self.emit != Vector3::zero()
     }
 
-    fn emissive(&self, _: Vector3, _: Vector3) -> bool {
-        true
+    fn to_description(&self) -> MaterialDescription {
+        MaterialDescription::Emissive {
+            emit: arr3(self.emit),
+        }
     }
 }
 
+/// Conductor with a GGX microfacet distribution. `roughness` is the GGX alpha;
+/// below `MIN_ROUGHNESS` the surface is treated as a perfect mirror.
 pub struct MetalMaterial {
     roughness: f32,
     albedo: Vector3,
+    /// The perceptual roughness `roughness` was made from, kept so saving
+    /// writes back exactly what was loaded.
+    fuzz: f32,
 }
 
+const MIN_ROUGHNESS: f32 = 1e-3;
+
 impl MetalMaterial {
     pub fn new(albedo: Vector3, roughness: f32) -> MetalMaterial {
-        MetalMaterial { roughness, albedo }
+        MetalMaterial {
+            roughness,
+            albedo,
+            fuzz: roughness.sqrt(),
+        }
+    }
+
+    /// Takes the perceptual roughness used by scene files and presets, which
+    /// used to be the radius of the fuzz added to the mirror direction.
+    /// Squaring it into the GGX alpha keeps them looking about the same.
+    pub fn from_fuzz(albedo: Vector3, fuzz: f32) -> MetalMaterial {
+        MetalMaterial {
+            roughness: fuzz * fuzz,
+            albedo,
+            fuzz,
+        }
+    }
+
+    fn fresnel(&self, cos_theta: f32) -> Vector3 {
+        let f0 = self.albedo;
+        f0 + (Vector3::one() - f0) * (1f32 - cos_theta).max(0f32).powi(5)
+    }
+
+    fn ggx_d(&self, n_dot_h: f32) -> f32 {
+        let a2 = self.roughness * self.roughness;
+        let d = n_dot_h * n_dot_h * (a2 - 1f32) + 1f32;
+        a2 / (PI * d * d)
+    }
+
+    fn smith_g1(&self, n_dot_v: f32) -> f32 {
+        let a2 = self.roughness * self.roughness;
+        2f32 * n_dot_v / (n_dot_v + (a2 + (1f32 - a2) * n_dot_v * n_dot_v).sqrt())
     }
 }
 
 impl RTMaterial for MetalMaterial {
-    fn attenuation(&self, _position: Vector3, _normal: Vector3) -> Vector3 {
+    fn sample(&self, wo: Vector3, hit: &HitData) -> Option<BSDFSample> {
+        let n = hit.normal;
+
+        if self.roughness < MIN_ROUGHNESS {
+            let wi = reflect(-wo, n);
+            let cos = wi.dot(n);
+            if cos <= 0f32 {
+                return None;
+            }
+            return Some(BSDFSample {
+                wi,
+                f: self.fresnel(cos) / cos,
+                pdf: 1f32,
+                delta: true,
+            });
+        }
+
+        // sample a microfacet normal proportional to D(h) * cos(theta_h)
+        let (u1, u2) = (rand_f32(), rand_f32());
+        let a2 = self.roughness * self.roughness;
+        let cos_theta = ((1f32 - u1) / (1f32 + (a2 - 1f32) * u1)).sqrt();
+        let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+        let phi = 2f32 * PI * u2;
+
+        let (t, b) = onb(n);
+        let h = t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + n * cos_theta;
+        let wi = reflect(-wo, h);
+
+        if wi.dot(n) <= 0f32 {
+            return None;
+        }
+
+        Some(BSDFSample {
+            wi,
+            f: self.eval(wo, wi, hit),
+            pdf: self.pdf(wo, wi, hit),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, wo: Vector3, wi: Vector3, hit: &HitData) -> Vector3 {
+        let n = hit.normal;
+        let (n_dot_o, n_dot_i) = (n.dot(wo), n.dot(wi));
+
+        if self.roughness < MIN_ROUGHNESS || n_dot_o <= 0f32 || n_dot_i <= 0f32 {
+            return Vector3::zero();
+        }
+
+        let h = (wo + wi).normalized();
+        let d = self.ggx_d(n.dot(h));
+        let g = self.smith_g1(n_dot_o) * self.smith_g1(n_dot_i);
+
+        self.fresnel(wo.dot(h)) * (d * g / (4f32 * n_dot_o * n_dot_i))
+    }
+
+    fn pdf(&self, wo: Vector3, wi: Vector3, hit: &HitData) -> f32 {
+        let n = hit.normal;
+
+        if self.roughness < MIN_ROUGHNESS || n.dot(wo) <= 0f32 || n.dot(wi) <= 0f32 {
+            return 0f32;
+        }
+
+        let h = (wo + wi).normalized();
+        let n_dot_h = n.dot(h);
+        self.ggx_d(n_dot_h) * n_dot_h / (4f32 * wo.dot(h).abs())
+    }
+
+    fn is_delta(&self) -> bool {
+        self.roughness < MIN_ROUGHNESS
+    }
+
+    fn albedo(&self, _hit: &HitData) -> Vector3 {
         self.albedo
     }
 
-    fn scatter(
-        &self,
-        in_ray: rendering::Ray,
-        position: Vector3,
-        normal: Vector3,
-    ) -> Option<rendering::Ray> {
-        Some(rendering::Ray::new(
-            position + (normal * EPSILON),
-            reflect(in_ray.direction, normal) + (rand_unit_vec() * self.roughness),
-        ))
+    fn to_description(&self) -> MaterialDescription {
+        MaterialDescription::Metal {
+            albedo: arr3(self.albedo),
+            roughness: self.fuzz,
+        }
     }
+}
 
-    fn emissive(&self, _position: Vector3, _normal: Vector3) -> bool {
-        false
+/// Clear refractive material such as glass or water. Picks reflection or
+/// refraction per ray with probability given by Schlick's Fresnel approximation.
+pub struct DielectricMaterial {
+    ior: f32,
+}
+
+impl DielectricMaterial {
+    pub fn new(ior: f32) -> DielectricMaterial {
+        DielectricMaterial { ior }
     }
 }
+
+impl RTMaterial for DielectricMaterial {
+    fn sample(&self, wo: Vector3, hit: &HitData) -> Option<BSDFSample> {
+        let eta = if hit.front_face {
+            1f32 / self.ior
+        } else {
+            self.ior
+        };
+
+        let cos_theta = wo.dot(hit.normal).min(1f32);
+        let refracted = refract(-wo, hit.normal, eta);
+        let reflectance = match refracted {
+            Some(_) => schlick(cos_theta, eta),
+            None => 1f32,
+        };
+
+        // both lobes are delta lobes picked with probability equal to their
+        // weight, so f * |cos| / pdf always comes out as one
+        let (wi, pdf) = match refracted {
+            Some(wi) if rand_f32() >= reflectance => (wi, 1f32 - reflectance),
+            _ => (reflect(-wo, hit.normal), reflectance),
+        };
+        let cos = wi.dot(hit.normal).abs();
+
+        Some(BSDFSample {
+            wi,
+            f: Vector3::one() * (pdf / cos),
+            pdf,
+            delta: true,
+        })
+    }
+
+    fn eval(&self, _wo: Vector3, _wi: Vector3, _hit: &HitData) -> Vector3 {
+        Vector3::zero()
+    }
+
+    fn pdf(&self, _wo: Vector3, _wi: Vector3, _hit: &HitData) -> f32 {
+        0f32
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn to_description(&self) -> MaterialDescription {
+        MaterialDescription::Dielectric { ior: self.ior }
+    }
+}
+
+/// Snell's law for a unit direction and a normal facing against it, `eta`
+/// being the ratio of refractive indices. `None` on total internal reflection.
+pub fn refract(dir: Vector3, normal: Vector3, eta: f32) -> Option<Vector3> {
+    let cos_i = (-dir).dot(normal).min(1f32);
+    let sin2_t = eta * eta * (1f32 - cos_i * cos_i);
+
+    if sin2_t > 1f32 {
+        return None;
+    }
+
+    let cos_t = (1f32 - sin2_t).sqrt();
+    Some(dir * eta + normal * (eta * cos_i - cos_t))
+}
+
+/// Schlick's approximation of the Fresnel reflectance.
+pub fn schlick(cos_theta: f32, eta: f32) -> f32 {
+    let r0 = ((1f32 - eta) / (1f32 + eta)).powi(2);
+    r0 + (1f32 - r0) * (1f32 - cos_theta).powi(5)
+}
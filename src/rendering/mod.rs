@@ -1,11 +1,26 @@
+mod aov;
+mod cryptomatte;
+mod denoiser;
+mod exposure;
 mod framebuffer;
+mod mask;
 mod materials;
+mod openexr;
 mod ray;
 mod ray_camera;
 mod renderer;
+pub mod rgbe;
+mod tonemap;
 
-pub use framebuffer::Framebuffer;
+pub use aov::{Aov, AovBuffers};
+pub use cryptomatte::{add_cryptomattes, name_hash, CRYPTOMATTE_RANKS};
+pub use denoiser::Denoiser;
+pub use exposure::{white_balance_gains, Exposure, NEUTRAL_WHITE_BALANCE};
+pub use framebuffer::{Framebuffer, ImageFormat};
+pub use mask::{id_color, MaskEntry, ObjectMask, BACKGROUND_ID};
 pub use materials::*;
+pub use openexr::{ExrSampleType, ExrWriter};
 pub use ray::Ray;
-pub use ray_camera::RayCamera;
+pub use ray_camera::{direction_from_yaw_pitch, yaw_pitch, Projection, RayCamera, DEFAULT_FOV};
 pub use renderer::Renderer;
+pub use tonemap::{srgb_eotf, srgb_oetf, ToneMapper, ToneMapping};
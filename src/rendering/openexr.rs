@@ -0,0 +1,112 @@
+use crate::rendering::Framebuffer;
+use exr::prelude::*;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrSampleType {
+    Half,
+    Float,
+}
+
+/// Collects named channels and writes them as a single part OpenEXR file.
+/// Layers follow the usual `layer.R` channel naming so compositors group them.
+pub struct ExrWriter {
+    width: usize,
+    height: usize,
+    channels: Vec<AnyChannel<FlatSamples>>,
+    attributes: Vec<(String, String)>,
+}
+
+impl ExrWriter {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels: Vec::new(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Adds the RGB channels of a framebuffer. An empty name writes the
+    /// default `R`, `G`, `B` channels that viewers show first.
+    pub fn add_layer(
+        &mut self,
+        name: &str,
+        framebuf: &Framebuffer,
+        scale: f32,
+        sample_type: ExrSampleType,
+    ) {
+        assert!(
+            framebuf.width == self.width && framebuf.height == self.height,
+            "layer {name} is {}x{}, expected {}x{}",
+            framebuf.width,
+            framebuf.height,
+            self.width,
+            self.height
+        );
+
+        let r = framebuf.data.iter().map(|c| c.x / scale).collect();
+        let g = framebuf.data.iter().map(|c| c.y / scale).collect();
+        let b = framebuf.data.iter().map(|c| c.z / scale).collect();
+
+        self.add_channel(&channel_name(name, "R"), r, sample_type);
+        self.add_channel(&channel_name(name, "G"), g, sample_type);
+        self.add_channel(&channel_name(name, "B"), b, sample_type);
+    }
+
+    pub fn add_channel(&mut self, name: &str, samples: Vec<f32>, sample_type: ExrSampleType) {
+        assert_eq!(samples.len(), self.width * self.height);
+
+        let samples = match sample_type {
+            ExrSampleType::Half => {
+                FlatSamples::F16(samples.into_iter().map(f16::from_f32).collect())
+            }
+            ExrSampleType::Float => FlatSamples::F32(samples),
+        };
+
+        self.channels.push(AnyChannel::new(name, samples));
+    }
+
+    /// Adds a channel of 32 bit unsigned integers, for ids that must come
+    /// back exactly.
+    pub fn add_uint_channel(&mut self, name: &str, samples: Vec<u32>) {
+        assert_eq!(samples.len(), self.width * self.height);
+
+        self.channels
+            .push(AnyChannel::new(name, FlatSamples::U32(samples)));
+    }
+
+    /// Adds a text attribute to the header. EXR text is Latin-1, so `value`
+    /// must not hold anything beyond that.
+    pub fn add_attribute(&mut self, name: &str, value: &str) {
+        self.attributes
+            .push((String::from(name), String::from(value)));
+    }
+
+    pub fn write<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        let mut attributes = LayerAttributes::default();
+        for (name, value) in &self.attributes {
+            attributes.other.insert(
+                Text::new_or_panic(name),
+                AttributeValue::Text(Text::new_or_panic(value)),
+            );
+        }
+
+        let layer = Layer::new(
+            (self.width, self.height),
+            attributes,
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(self.channels.into()),
+        );
+
+        Image::from_layer(layer).write().to_file(path)
+    }
+}
+
+fn channel_name(layer: &str, channel: &str) -> String {
+    if layer.is_empty() {
+        String::from(channel)
+    } else {
+        format!("{layer}.{channel}")
+    }
+}
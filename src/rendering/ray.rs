@@ -31,10 +31,10 @@ impl Ray {
     pub fn transform(&self, t: &Transform) -> Self {
         Self {
             origin: self.origin.transform_with(t.inv),
-            direction: self
-                .direction
-                .transform_with(t.inv.transposed())
-                .normalized(),
+            // directions ignore the translation part of the matrix
+            direction: (self.direction.transform_with(t.inv)
+                - Vector3::zero().transform_with(t.inv))
+            .normalized(),
         }
     }
 }
@@ -1,15 +1,61 @@
-use crate::rendering::Ray;
-use rand::Rng;
+use crate::rendering::{Exposure, Ray};
+use crate::scene::models::Scene;
+use crate::utils::{onb, rand_in_unit_disk, rand_range};
 use raylib::drawing::RaylibDrawHandle;
 use raylib::math::Vector3;
+use std::f32::consts::PI;
 use std::ops::Add;
 
+/// Vertical field of view matching the fixed 1.5 unit viewport at distance 1
+/// the camera used before the field of view was configurable.
+pub const DEFAULT_FOV: f32 = 73.7398f32;
+
+/// How image positions map to rays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays covering `width` world units across the image.
+    Orthographic {
+        width: f32,
+    },
+    /// Equidistant fisheye whose image circle, touching the top and bottom of
+    /// the image, spans the camera's `fov`.
+    Fisheye,
+    /// Full 360° by 180° latitude-longitude panorama around the view direction.
+    /// Meant for a 2:1 image.
+    Equirectangular,
+}
+
+/// A thin lens camera. With `aperture` at zero it is a pinhole camera and
+/// `focus_distance` has no effect. The lens only applies to the perspective
+/// and orthographic projections.
+#[derive(Clone)]
 pub struct RayCamera {
     pub position: Vector3,
     pub direction: Vector3,
+    /// Degrees above the horizon, kept in sync with `direction` by
+    /// `update_direction` and `look_at`.
     pub pitch: f32,
+    /// Degrees around the y axis, 0 looking along +x and 90 along +z.
     pub yaw: f32,
-    near_plane: f32,
+    /// Which way is up in the image, before `roll`. Needn't be perpendicular
+    /// to `direction`.
+    pub up: Vector3,
+    /// Rotation of the image around the view direction in degrees,
+    /// counter-clockwise as seen by the camera.
+    pub roll: f32,
+    pub projection: Projection,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    /// Radius of the lens.
+    pub aperture: f32,
+    /// Distance along `direction` of the plane that is in perfect focus.
+    pub focus_distance: f32,
+    /// How bright the rendered radiance comes out, applied when the image is
+    /// converted for output. `set_f_number` keeps its f-number in line with
+    /// `aperture`.
+    pub exposure: Exposure,
+    /// Viewport size at distance one from the lens.
     viewport_size: Vector3,
 }
 
@@ -20,49 +66,195 @@ impl RayCamera {
             direction: Vector3::new(0f32, 0f32, 1f32),
             pitch: 0f32,
             yaw: 90f32,
-            near_plane: 1f32,
+            up: Vector3::new(0f32, 1f32, 0f32),
+            roll: 0f32,
+            projection: Projection::Perspective,
+            fov: DEFAULT_FOV,
+            aperture: 0f32,
+            focus_distance: 1f32,
+            exposure: Exposure::default(),
             viewport_size: Vector3::new(2f32 * 16f32 / 9f32, 2f32, 0f32),
         }
     }
 
+    /// A camera at `eye` looking at `target`, with `up` pointing towards the
+    /// top of the image.
+    pub fn look_at(eye: Vector3, target: Vector3, up: Vector3) -> RayCamera {
+        let mut camera = RayCamera::new(eye);
+        camera.set_look_at(target, up);
+        camera
+    }
+
+    /// Points the camera at `target` and updates `yaw` and `pitch` to match.
+    pub fn set_look_at(&mut self, target: Vector3, up: Vector3) {
+        let dir = target - self.position;
+        if dir.length() > 0f32 {
+            self.direction = dir.normalized();
+        }
+        self.up = up;
+        (self.yaw, self.pitch) = yaw_pitch(self.direction);
+    }
+
+    /// The point `distance` away along the view direction, the target of the
+    /// camera in look-at form.
+    pub fn target(&self, distance: f32) -> Vector3 {
+        self.position + self.direction.normalized() * distance
+    }
+
+    /// Sets `direction` from `yaw` and `pitch`.
+    pub fn update_direction(&mut self) {
+        self.direction = direction_from_yaw_pitch(self.yaw, self.pitch);
+    }
+
+    /// Forward, right and up unit vectors of the image plane. Falls back to an
+    /// arbitrary but stable right vector when looking along `up`.
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = self.direction.normalized();
+
+        let mut right = self.up.cross(forward);
+        if right.length() < 1e-4f32 {
+            right = onb(forward).0;
+        }
+        let right = right.normalized();
+        let up = forward.cross(right);
+
+        let (sin, cos) = self.roll.to_radians().sin_cos();
+        (forward, right * cos - up * sin, up * cos + right * sin)
+    }
+
     pub fn update_viewport(&mut self, screen_width: usize, screen_height: usize) {
+        let height = 2f32 * (self.fov.to_radians() / 2f32).tan();
         self.viewport_size = Vector3::new(
-            (screen_width as f32 / screen_height as f32) * 1.5f32,
-            1.5f32,
+            (screen_width as f32 / screen_height as f32) * height,
+            height,
             0f32,
         )
     }
 
+    /// Focal length of the 35 mm camera with the same field of view, with
+    /// scene units taken as meters.
+    pub fn focal_length(&self) -> f32 {
+        // half the 24 mm height of a full frame sensor
+        0.012f32 / (self.fov.to_radians() / 2f32).tan()
+    }
+
+    /// Sets the f-number of the exposure and opens the lens to match it at
+    /// the current field of view.
+    pub fn set_f_number(&mut self, f_number: f32) {
+        self.exposure.f_number = f_number;
+        self.aperture = self.focal_length() / (2f32 * f_number);
+    }
+
+    /// Sets `focus_distance` to whatever the ray through the center of the
+    /// image hits. Returns the new distance, or `None` if the ray hits nothing
+    /// and the focus is left alone.
+    pub fn autofocus(&mut self, scene: &Scene) -> Option<f32> {
+        let (dir, _, _) = self.basis();
+        let (_, hit) = scene.intersect(&Ray::new(self.position, dir))?;
+
+        self.focus_distance = (hit.position - self.position).dot(dir);
+        Some(self.focus_distance)
+    }
+
+    /// Ray through a random point of the pixel at (`screen_x`, `screen_y`),
+    /// row 0 being the top of the image. `None` for pixels outside the image
+    /// circle of a fisheye projection.
     pub fn gen_primary_ray(
         &self,
         screen_x: usize,
         screen_y: usize,
         screen_width: usize,
         screen_height: usize,
-    ) -> Ray {
-        let mut rng = rand::thread_rng();
-        let adjacent = Vector3::new(0f32, 1f32, 0f32)
-            .cross(self.direction)
-            .normalized();
-        let local_up = adjacent.cross(self.direction).normalized();
-        let bottom_left = adjacent
-            .scale_by(-self.viewport_size.x / 2f32)
-            .add(local_up.scale_by(-self.viewport_size.y / 2f32));
-
-        let dir = bottom_left
-            .add(adjacent.scale_by(
-                (self.viewport_size.x)
-                    * ((screen_x as f32 + rng.gen_range(-0.5f32..0.5f32)) / screen_width as f32),
-            ))
-            .add(local_up.scale_by(
-                (self.viewport_size.y)
-                    * ((screen_y as f32 + rng.gen_range(-0.5f32..0.5f32)) / screen_height as f32),
-            ))
-            .add(self.direction.scale_by(self.near_plane))
-            .normalized();
-
-        return Ray::new(self.position, dir);
+    ) -> Option<Ray> {
+        let (forward, adjacent, local_up) = self.basis();
+
+        // position on the image in [-1, 1], y pointing up
+        let u = 2f32 * (screen_x as f32 + 0.5f32 + rand_range(-0.5f32, 0.5f32))
+            / screen_width as f32
+            - 1f32;
+        let v = 1f32
+            - 2f32 * (screen_y as f32 + 0.5f32 + rand_range(-0.5f32, 0.5f32))
+                / screen_height as f32;
+        let aspect = screen_width as f32 / screen_height as f32;
+
+        match self.projection {
+            Projection::Perspective => {
+                let dir = forward
+                    .add(adjacent.scale_by(u * self.viewport_size.x / 2f32))
+                    .add(local_up.scale_by(v * self.viewport_size.y / 2f32));
+                Some(self.lens_ray(self.position, dir, adjacent, local_up))
+            }
+            Projection::Orthographic { width } => {
+                let origin = self
+                    .position
+                    .add(adjacent.scale_by(u * width / 2f32))
+                    .add(local_up.scale_by(v * width / aspect / 2f32));
+                Some(self.lens_ray(origin, forward, adjacent, local_up))
+            }
+            Projection::Fisheye => {
+                // equidistant: the angle to the view direction grows linearly
+                // with the distance from the image center
+                let (x, y) = (u * aspect, v);
+                let r = (x * x + y * y).sqrt();
+                if r > 1f32 {
+                    return None;
+                }
+
+                let theta = r * self.fov.to_radians() / 2f32;
+                let phi = y.atan2(x);
+                let dir = forward.scale_by(theta.cos())
+                    + (adjacent.scale_by(phi.cos()) + local_up.scale_by(phi.sin()))
+                        .scale_by(theta.sin());
+                Some(Ray::new(self.position, dir.normalized()))
+            }
+            Projection::Equirectangular => {
+                let (lon, lat) = (u * PI, v * PI / 2f32);
+                let dir = forward.scale_by(lat.cos() * lon.cos())
+                    + adjacent.scale_by(lat.cos() * lon.sin())
+                    + local_up.scale_by(lat.sin());
+                Some(Ray::new(self.position, dir.normalized()))
+            }
+        }
+    }
+
+    /// Turns the pinhole ray from `origin` along `dir` into one through a
+    /// random point on the lens, both meeting on the focus plane.
+    fn lens_ray(&self, origin: Vector3, dir: Vector3, adjacent: Vector3, local_up: Vector3) -> Ray {
+        if self.aperture <= 0f32 {
+            return Ray::new(origin, dir.normalized());
+        }
+
+        // `dir` has unit length along the view direction, so this point lies
+        // on the focus plane
+        let focus_point = origin + dir * self.focus_distance;
+        let (lx, ly) = rand_in_unit_disk();
+        let lens_point = origin + (adjacent * lx + local_up * ly) * self.aperture;
+
+        Ray::new(lens_point, (focus_point - lens_point).normalized())
     }
 
     pub fn handle_input(&self, _handle: &RaylibDrawHandle<'_>) {}
 }
+
+/// Unit view direction for the yaw/pitch form the viewer uses, in degrees.
+pub fn direction_from_yaw_pitch(yaw: f32, pitch: f32) -> Vector3 {
+    let (yaw, pitch) = (yaw.to_radians(), pitch.to_radians());
+    Vector3::new(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    )
+}
+
+/// Yaw and pitch in degrees of a view direction. Looking straight up or down
+/// any yaw works; 90 is returned then.
+pub fn yaw_pitch(direction: Vector3) -> (f32, f32) {
+    let dir = direction.normalized();
+    let pitch = dir.y.clamp(-1f32, 1f32).asin().to_degrees();
+    let yaw = if dir.x.abs() < 1e-6f32 && dir.z.abs() < 1e-6f32 {
+        90f32
+    } else {
+        dir.z.atan2(dir.x).to_degrees()
+    };
+    (yaw, pitch)
+}
@@ -1,15 +1,26 @@
 use crate::rendering;
-use crate::rendering::{Framebuffer, RTMaterial, RayCamera};
+use crate::rendering::aov::AovSample;
+use crate::rendering::{AovBuffers, Framebuffer, ObjectMask, RTMaterial, RayCamera, BACKGROUND_ID};
 use crate::scene::models::Scene;
+use crate::scene::HitData;
+use crate::utils::seed_rng;
 use raylib::math::Vector3;
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
 
 pub const EPSILON: f32 = 0.0001f32;
+/// Fraction of the distance to a sampled light point a shadow ray stops
+/// short of, so it doesn't hit the light itself.
+const SHADOW_EPSILON: f32 = 0.001f32;
 
 pub struct Renderer<'a> {
     pub num_samples: u32,
+    pub num_bounces: i32,
+    /// When set, every pixel sample draws from an RNG seeded by this value, the
+    /// sample index and the pixel index, making renders reproducible.
+    pub seed: Option<u64>,
     scene: &'a Scene,
-    num_bounces: i32,
 }
 
 impl Renderer<'_> {
@@ -18,6 +29,7 @@ impl Renderer<'_> {
             num_samples: 0,
             scene,
             num_bounces: 10,
+            seed: None,
         };
     }
 
@@ -25,166 +37,308 @@ impl Renderer<'_> {
         self.num_samples = 0;
     }
 
-    pub fn render_normals(
+    /// Adds one sample per pixel of the object and material seen first to
+    /// `mask`. With a seed the samples land where the image's do, so mattes
+    /// line up with its edges.
+    pub fn render_object_mask(
         &mut self,
         width: usize,
         height: usize,
-        normal_buffer: &mut Framebuffer,
+        mask: &mut ObjectMask,
         camera: &mut RayCamera,
     ) {
         camera.update_viewport(width, height);
 
-        normal_buffer
-            .data
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, normal)| {
+        let ids: Vec<(i32, i32)> = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
                 let x = i % width;
                 let y = i / width;
-                let ray = camera.gen_primary_ray(x, y, width, height);
-
-                let hit_opt = self.scene.intersect(&ray);
+                if let Some(seed) = self.seed {
+                    seed_rng(pixel_seed(seed, mask.num_samples, i));
+                }
+                let hit_opt = camera
+                    .gen_primary_ray(x, y, width, height)
+                    .and_then(|ray| self.scene.intersect(&ray));
                 match hit_opt {
-                    Some((_, hit)) => {
-                        *normal = hit.normal;
-                    }
-                    None => *normal = Vector3::new(0.0, 0.0, 0.0),
+                    Some((_, hit)) => (hit.object_id as i32, hit.material_id as i32),
+                    None => (BACKGROUND_ID, BACKGROUND_ID),
                 }
             })
+            .collect();
+
+        mask.accumulate(&ids);
     }
-    pub fn render_bvh_hits(
+
+    pub fn render_sample(
         &mut self,
         width: usize,
         height: usize,
-        hit_buffer: &mut Framebuffer,
+        frame_buffer: &mut Framebuffer,
         camera: &mut RayCamera,
     ) {
-        camera.update_viewport(width, height);
-
-        hit_buffer
-            .data
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, normal)| {
-                let x = i % width;
-                let y = i / width;
-                let ray = camera.gen_primary_ray(x, y, width, height);
-
-                let hit_opt = self.scene.intersect(&ray);
-                match hit_opt {
-                    Some((_, hit)) => {
-                        let s = hit.node_hits as f32 / 10f32;
-                        let hits = Vector3::new(s, s, s);
-                        *normal = hits;
-                    }
-                    None => *normal = Vector3::new(0.0, 0.0, 0.0),
-                }
-            })
+        let mut no_aovs = AovBuffers::new(width, height, &[]);
+        self.render_sample_aovs(width, height, frame_buffer, &mut no_aovs, camera);
     }
 
-    pub fn render_object_mask(
+    /// Adds one sample per pixel to `frame_buffer` and to every buffer in
+    /// `aovs`, from the same camera rays.
+    pub fn render_sample_aovs(
         &mut self,
         width: usize,
         height: usize,
-        mask_buffer: &mut Framebuffer,
+        frame_buffer: &mut Framebuffer,
+        aovs: &mut AovBuffers,
         camera: &mut RayCamera,
     ) {
-        todo!()
+        camera.update_viewport(width, height);
+
+        let render_pixel = |(i, pixel): (usize, &mut Vector3)| {
+            let x = i % width;
+            let y = i / width;
+            if let Some(seed) = self.seed {
+                seed_rng(pixel_seed(seed, self.num_samples, i));
+            }
+            let mut aov = AovSample::default();
+            if let Some(ray) = camera.gen_primary_ray(x, y, width, height) {
+                *pixel += self.cast_iter(ray, self.num_bounces, &mut aov);
+            }
+            aov
+        };
+
+        if aovs.is_empty() {
+            frame_buffer
+                .data
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|pixel| {
+                    render_pixel(pixel);
+                });
+        } else {
+            let samples: Vec<AovSample> = frame_buffer
+                .data
+                .par_iter_mut()
+                .enumerate()
+                .map(render_pixel)
+                .collect();
+            aovs.accumulate(&samples, self.num_samples == 0);
+        }
+
+        self.num_samples += 1;
     }
 
-    pub fn render_sample(
+    /// Like `render_sample_aovs` without a color buffer, tracing camera rays
+    /// only. Cheap enough for previews of the surface AOVs; the lighting ones
+    /// stay black.
+    pub fn render_sample_surface_aovs(
         &mut self,
         width: usize,
         height: usize,
-        frame_buffer: &mut Framebuffer,
-        //snormal_buffer: &mut Framebuffer,
+        aovs: &mut AovBuffers,
         camera: &mut RayCamera,
     ) {
         camera.update_viewport(width, height);
 
-        frame_buffer
-            .data
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, pixel)| {
+        let samples: Vec<AovSample> = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
                 let x = i % width;
                 let y = i / width;
-                let ray = camera.gen_primary_ray(x, y, width, height);
-
-                *pixel += self.cast_iter(ray, self.num_bounces as i32);
-            });
+                if let Some(seed) = self.seed {
+                    seed_rng(pixel_seed(seed, self.num_samples, i));
+                }
+                let mut aov = AovSample::default();
+                if let Some(ray) = camera.gen_primary_ray(x, y, width, height) {
+                    if let Some((obj, hit)) = self.scene.intersect(&ray) {
+                        let dist = hit.position.distance_to(ray.origin);
+                        aov.set_hit(&hit, dist, obj.material().albedo(&hit));
+                    }
+                }
+                aov
+            })
+            .collect();
+        aovs.accumulate(&samples, self.num_samples == 0);
 
         self.num_samples += 1;
     }
 
-    fn cast_iter(&self, ray: rendering::Ray, depth: i32) -> Vector3 {
-        let mut result = Vector3::new(1f32, 1f32, 1f32);
+    /// Traces a path starting with `ray` and returns the light it carries
+    /// back, filling `aov` with the first hit and the direct/indirect split.
+    fn cast_iter(&self, ray: rendering::Ray, depth: i32, aov: &mut AovSample) -> Vector3 {
+        let mut radiance = Vector3::zero();
+        // the part of `radiance` that took at most one bounce
+        let mut direct = Vector3::zero();
+        let mut throughput = Vector3::one();
         let mut current_ray = ray;
+        // origin and pdf of the last non-delta BSDF sample, for weighting
+        // emitters it runs into against direct light sampling
+        let mut prev_sample: Option<(Vector3, f32)> = None;
 
-        for _ in 0..depth {
+        for bounce in 0..depth {
             let hit = self.scene.intersect(&current_ray);
             match hit {
                 Some((obj, hit_data)) => {
                     let material = obj.material();
-                    let position = hit_data.position;
-                    let normal = hit_data.normal;
-
-                    let scatter =
-                        material.scatter(current_ray, position + (normal * EPSILON), normal);
-                    let attenuation = material.attenuation(position, normal);
-                    let emissive = material.emissive(position, normal);
-
-                    match scatter {
-                        Some(scatter_ray) => {
-                            result *= attenuation;
-                            current_ray.origin = scatter_ray.origin;
-                            current_ray.direction = scatter_ray.direction;
-                        }
-                        None => {
-                            if emissive {
-                                return result * attenuation;
-                            } else {
-                                return Vector3::new(0f32, 0f32, 0f32);
+                    let wo = -current_ray.direction.normalized();
+
+                    if bounce == 0 {
+                        let dist = hit_data.position.distance_to(current_ray.origin);
+                        aov.set_hit(&hit_data, dist, material.albedo(&hit_data));
+                    }
+
+                    let emitted = material.emitted(wo, &hit_data);
+                    if emitted != Vector3::zero() {
+                        let weight = match prev_sample {
+                            Some((origin, bsdf_pdf)) => {
+                                let light_pdf =
+                                    self.scene.emitter_pdf(obj.as_ref(), origin, &hit_data);
+                                power_heuristic(bsdf_pdf, light_pdf)
                             }
+                            None => 1f32,
+                        };
+                        let contribution = throughput * emitted * weight;
+                        radiance += contribution;
+                        if bounce <= 1 {
+                            direct += contribution;
                         }
                     }
+
+                    // lights are sampled even where the BSDF sample below
+                    // fails, e.g. a rough metal reflecting below the horizon
+                    if !material.is_delta() {
+                        let contribution =
+                            throughput * self.direct_light(wo, &hit_data, material.as_ref());
+                        radiance += contribution;
+                        if bounce == 0 {
+                            direct += contribution;
+                        }
+                    }
+
+                    let Some(sample) = material.sample(wo, &hit_data) else {
+                        break;
+                    };
+                    if sample.pdf <= 0f32 {
+                        break;
+                    }
+
+                    throughput *= sample.f * (sample.wi.dot(hit_data.normal).abs() / sample.pdf);
+                    prev_sample = if sample.delta {
+                        None
+                    } else {
+                        Some((hit_data.position, sample.pdf))
+                    };
+                    current_ray = spawn_ray(&hit_data, sample.wi);
+                }
+                None => {
+                    let environment = self.scene.environment();
+                    let dir = current_ray.direction.normalized();
+                    let weight = match prev_sample {
+                        Some((_, bsdf_pdf)) => power_heuristic(bsdf_pdf, environment.pdf(dir)),
+                        None => 1f32,
+                    };
+                    let contribution = throughput * environment.radiance(dir) * weight;
+                    radiance += contribution;
+                    if bounce <= 1 {
+                        direct += contribution;
+                    }
+                    break;
                 }
-                None => return result * sky_color(&current_ray), // None => Vector3::new(0.0, 0.0, 0.0), //sky_color(ray)
             }
         }
 
-        return Vector3::new(0f32, 0f32, 0f32);
+        aov.direct = direct;
+        aov.indirect = radiance - direct;
+        radiance
+    }
+
+    /// Next-event estimation: light reaching `hit` directly from emitters,
+    /// analytic lights and the environment.
+    fn direct_light(&self, wo: Vector3, hit: &HitData, material: &dyn RTMaterial) -> Vector3 {
+        self.sample_emitter(wo, hit, material)
+            + self.sample_lights(wo, hit, material)
+            + self.sample_environment(wo, hit, material)
     }
 
-    fn cast(&self, ray: rendering::Ray, depth: i32) -> Vector3 {
-        if depth <= 0 {
-            return Vector3::new(0f32, 0f32, 0f32);
+    /// Light arriving at `hit` from a point sampled on an emitter, MIS
+    /// weighted against the BSDF sampling done in `cast_iter`.
+    fn sample_emitter(&self, wo: Vector3, hit: &HitData, material: &dyn RTMaterial) -> Vector3 {
+        let Some((light, mut light_hit, light_pdf)) = self.scene.sample_emitter(hit.position)
+        else {
+            return Vector3::zero();
+        };
+        if light_pdf <= 0f32 {
+            return Vector3::zero();
         }
 
-        let hit = self.scene.intersect(&ray);
-        match hit {
-            Some((obj, hit_data)) => {
-                let material = obj.material();
-                let position = hit_data.position;
-                let normal = hit_data.normal;
-
-                let scatter = material.scatter(ray, position + (normal * EPSILON), normal);
-                let attenuation = material.attenuation(position, normal);
-                let emissive = material.emissive(position, normal);
-
-                match scatter {
-                    Some(scatter_ray) => attenuation * self.cast(scatter_ray, depth - 1),
-                    None => {
-                        if emissive {
-                            attenuation
-                        } else {
-                            Vector3::new(0f32, 0f32, 0f32)
-                        }
-                    }
-                }
+        let to_light = light_hit.position - hit.position;
+        let dist = to_light.length();
+        let wi = to_light / dist;
+
+        let f = material.eval(wo, wi, hit);
+        if f == Vector3::zero() {
+            return Vector3::zero();
+        }
+
+        let shadow_ray = spawn_ray(hit, wi);
+        if self
+            .scene
+            .occluded(&shadow_ray, dist * (1f32 - SHADOW_EPSILON))
+        {
+            return Vector3::zero();
+        }
+
+        light_hit.set_face_normal(&shadow_ray);
+        let emitted = light.material().emitted(-wi, &light_hit);
+        let weight = power_heuristic(light_pdf, material.pdf(wo, wi, hit));
+
+        f * emitted * (wi.dot(hit.normal).abs() * weight / light_pdf)
+    }
+
+    /// Light from the scene's analytic lights. They are points or directions,
+    /// so BSDF sampling can never find them and no MIS is needed.
+    fn sample_lights(&self, wo: Vector3, hit: &HitData, material: &dyn RTMaterial) -> Vector3 {
+        let mut radiance = Vector3::zero();
+
+        for light in self.scene.lights() {
+            let Some(sample) = light.sample(hit.position) else {
+                continue;
+            };
+
+            let f = material.eval(wo, sample.wi, hit);
+            if f == Vector3::zero() {
+                continue;
             }
-            None => sky_color(&ray), // None => Vector3::new(0.0, 0.0, 0.0), //sky_color(ray)
+
+            let shadow_ray = spawn_ray(hit, sample.wi);
+            if self.scene.occluded(&shadow_ray, sample.dist) {
+                continue;
+            }
+
+            radiance += f * sample.radiance * sample.wi.dot(hit.normal).abs();
         }
+
+        radiance
+    }
+
+    /// Light arriving at `hit` from a direction importance sampled on the
+    /// environment, MIS weighted like `sample_emitter`.
+    fn sample_environment(&self, wo: Vector3, hit: &HitData, material: &dyn RTMaterial) -> Vector3 {
+        let environment = self.scene.environment();
+        let Some((wi, env_pdf)) = environment.sample() else {
+            return Vector3::zero();
+        };
+
+        let f = material.eval(wo, wi, hit);
+        if f == Vector3::zero() {
+            return Vector3::zero();
+        }
+
+        if self.scene.occluded(&spawn_ray(hit, wi), f32::INFINITY) {
+            return Vector3::zero();
+        }
+
+        let weight = power_heuristic(env_pdf, material.pdf(wo, wi, hit));
+        f * environment.radiance(wi) * (wi.dot(hit.normal).abs() * weight / env_pdf)
     }
 
     pub fn render_full(
@@ -194,24 +348,61 @@ impl Renderer<'_> {
         frame_buffer: &mut Framebuffer,
         samples: u32,
         camera: &mut RayCamera,
+    ) {
+        let mut no_aovs = AovBuffers::new(width, height, &[]);
+        self.render_full_aovs(width, height, frame_buffer, &mut no_aovs, samples, camera);
+    }
+
+    /// Renders `samples` samples per pixel into `frame_buffer` and `aovs` and
+    /// averages them.
+    pub fn render_full_aovs(
+        &mut self,
+        width: usize,
+        height: usize,
+        frame_buffer: &mut Framebuffer,
+        aovs: &mut AovBuffers,
+        samples: u32,
+        camera: &mut RayCamera,
     ) {
         frame_buffer.clear();
+        aovs.clear();
 
         for _ in 0..samples {
-            self.render_sample(width, height, frame_buffer, camera);
+            self.render_sample_aovs(width, height, frame_buffer, aovs, camera);
         }
 
         frame_buffer.normalize(self.num_samples as f32);
+        aovs.normalize(self.num_samples as f32);
 
         self.num_samples = 0;
     }
 }
 
-fn sky_color(ray: &rendering::Ray) -> Vector3 {
-    let t = 0.5f32 * (ray.direction.y + 1.0f32);
-    return Vector3::new(
-        (1f32 - t) + (t * 138f32 / 255f32),
-        (1f32 - t) + (t * 188f32 / 255f32),
-        1f32,
-    );
+/// Offsets the origin to the side of the surface `dir` points to, so the new
+/// ray doesn't hit the surface it starts on.
+fn spawn_ray(hit: &HitData, dir: Vector3) -> rendering::Ray {
+    let offset = if dir.dot(hit.normal) > 0f32 {
+        hit.normal * EPSILON
+    } else {
+        hit.normal * -EPSILON
+    };
+
+    rendering::Ray::new(hit.position + offset, dir)
+}
+
+/// Veach's power heuristic (beta = 2) for the strategy with density `pdf_a`.
+fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let (a2, b2) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a2 + b2 == 0f32 {
+        return 0f32;
+    }
+    a2 / (a2 + b2)
+}
+
+fn pixel_seed(seed: u64, sample: u32, pixel: usize) -> u64 {
+    // splitmix64 finalizer so neighbouring pixels get unrelated streams
+    let mut z = seed ^ ((sample as u64) << 40) ^ (pixel as u64);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
 }
@@ -0,0 +1,161 @@
+use crate::rendering::Framebuffer;
+use raylib::math::Vector3;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Writes a Radiance `.hdr` file with uncompressed RGBE scanlines.
+pub fn write_hdr<P: AsRef<Path>>(path: P, framebuf: &Framebuffer, scale: f32) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        framebuf.height, framebuf.width
+    )?;
+
+    let pixels: Vec<u8> = framebuf
+        .data
+        .iter()
+        .flat_map(|c| to_rgbe(*c / scale))
+        .collect();
+    out.write_all(&pixels)?;
+    out.flush()
+}
+
+/// Reads a Radiance `.hdr` file, accepting both flat and run length encoded scanlines.
+pub fn read_hdr<P: AsRef<Path>>(path: P) -> io::Result<Framebuffer> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+
+    input.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("missing radiance signature"));
+    }
+
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of header"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("only 32-bit_rle_rgbe is supported"));
+        }
+    }
+
+    line.clear();
+    input.read_line(&mut line)?;
+    let (width, height) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => (
+            w.parse().map_err(|_| invalid_data("bad image width"))?,
+            h.parse().map_err(|_| invalid_data("bad image height"))?,
+        ),
+        _ => return Err(invalid_data("only -Y h +X w orientation is supported")),
+    };
+
+    let mut framebuf = Framebuffer::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for y in 0..height {
+        read_scanline(&mut input, &mut scanline)?;
+        for (x, rgbe) in scanline.iter().enumerate() {
+            framebuf.set_pixel(x, y, from_rgbe(*rgbe));
+        }
+    }
+
+    Ok(framebuf)
+}
+
+fn read_scanline<R: Read>(input: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut first = [0u8; 4];
+    input.read_exact(&mut first)?;
+
+    let rle = (8..0x8000).contains(&width)
+        && first[0] == 2
+        && first[1] == 2
+        && ((first[2] as usize) << 8 | first[3] as usize) == width;
+
+    if !rle {
+        scanline[0] = first;
+        for px in scanline.iter_mut().skip(1) {
+            input.read_exact(px)?;
+        }
+        return Ok(());
+    }
+
+    // new style RLE stores each component as its own run length encoded plane
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            input.read_exact(&mut count)?;
+            let count = count[0] as usize;
+
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return Err(invalid_data("scanline run overflows image width"));
+                }
+                let mut value = [0u8; 1];
+                input.read_exact(&mut value)?;
+                scanline[x..x + run]
+                    .iter_mut()
+                    .for_each(|px| px[c] = value[0]);
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad scanline length"));
+                }
+                for px in scanline[x..x + count].iter_mut() {
+                    let mut value = [0u8; 1];
+                    input.read_exact(&mut value)?;
+                    px[c] = value[0];
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+pub fn to_rgbe(color: Vector3) -> [u8; 4] {
+    // NaN is written as black and infinity as the brightest encodable value
+    let [r, g, b] =
+        [color.x, color.y, color.z].map(|c| if c.is_nan() { 0f32 } else { c.min(f32::MAX) });
+    let v = r.max(g).max(b);
+
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1), limited to the exponents a byte holds;
+    // the mantissa casts saturate at 255 for values above the top one
+    let e = (v.log2().floor() as i32 + 1).clamp(-128, 127);
+    let f = 256f32 / 2f32.powi(e);
+
+    [(r * f) as u8, (g * f) as u8, (b * f) as u8, (e + 128) as u8]
+}
+
+pub fn from_rgbe(rgbe: [u8; 4]) -> Vector3 {
+    if rgbe[3] == 0 {
+        return Vector3::zero();
+    }
+
+    let f = 2f32.powi(rgbe[3] as i32 - 136);
+
+    Vector3::new(
+        (rgbe[0] as f32 + 0.5) * f,
+        (rgbe[1] as f32 + 0.5) * f,
+        (rgbe[2] as f32 + 0.5) * f,
+    )
+}
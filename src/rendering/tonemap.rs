@@ -0,0 +1,171 @@
+use crate::utils::luminance;
+use raylib::math::Vector3;
+
+/// Compresses linear radiance into the [0, 1] range of a display.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ToneMapper {
+    /// Cuts everything off at one.
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance, never quite reaching white.
+    Reinhard,
+    /// Reinhard with luminance `white` and above mapping to white.
+    ExtendedReinhard { white: f32 },
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+    /// Stephen Hill's fit of the ACES reference and sRGB output transforms.
+    AcesFitted,
+}
+
+impl ToneMapper {
+    /// Maps linear radiance to linear display values in [0, 1].
+    pub fn apply(&self, color: Vector3) -> Vector3 {
+        let mapped = match *self {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1f32 + l)),
+            ToneMapper::ExtendedReinhard { white } => {
+                let white2 = (white * white).max(f32::EPSILON);
+                scale_luminance(color, |l| l * (1f32 + l / white2) / (1f32 + l))
+            }
+            ToneMapper::Hable => {
+                // the exposure bias of the original brings middle gray back up
+                let white_scale = 1f32 / hable_partial(HABLE_WHITE);
+                Vector3::new(
+                    hable_partial(2f32 * color.x),
+                    hable_partial(2f32 * color.y),
+                    hable_partial(2f32 * color.z),
+                ) * white_scale
+            }
+            ToneMapper::AcesFitted => {
+                let v = mul3(&ACES_INPUT, color);
+                let v = Vector3::new(rrt_odt_fit(v.x), rrt_odt_fit(v.y), rrt_odt_fit(v.z));
+                mul3(&ACES_OUTPUT, v)
+            }
+        };
+
+        Vector3::new(
+            mapped.x.clamp(0f32, 1f32),
+            mapped.y.clamp(0f32, 1f32),
+            mapped.z.clamp(0f32, 1f32),
+        )
+    }
+}
+
+/// Everything between linear radiance and the integer values of an 8 or 16
+/// bit image: the tone mapper, the sRGB transfer function and, optionally,
+/// dithering to hide banding in smooth gradients.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    pub dither: bool,
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneMapper, dither: bool) -> ToneMapping {
+        ToneMapping { operator, dither }
+    }
+
+    /// Encodes `color`, the radiance at pixel (`x`, `y`), as integer values in
+    /// [0, `max`]. The dither only depends on the pixel, so the same image
+    /// always encodes the same way.
+    pub fn encode(&self, color: Vector3, x: usize, y: usize, max: f32) -> [f32; 3] {
+        let mapped = self.operator.apply(color);
+        let channels = [mapped.x, mapped.y, mapped.z];
+
+        [0, 1, 2].map(|channel| {
+            let noise = if self.dither {
+                triangular_noise(x, y, channel)
+            } else {
+                0f32
+            };
+            (srgb_oetf(channels[channel]) * max + noise)
+                .round()
+                .clamp(0f32, max)
+        })
+    }
+}
+
+/// The sRGB opto-electronic transfer function for linear values in [0, 1].
+pub fn srgb_oetf(linear: f32) -> f32 {
+    if linear <= 0.0031308f32 {
+        12.92f32 * linear
+    } else {
+        1.055f32 * linear.powf(1f32 / 2.4f32) - 0.055f32
+    }
+}
+
+/// The inverse of `srgb_oetf`, turning encoded values in [0, 1] back into
+/// linear ones.
+pub fn srgb_eotf(encoded: f32) -> f32 {
+    if encoded <= 0.04045f32 {
+        encoded / 12.92f32
+    } else {
+        ((encoded + 0.055f32) / 1.055f32).powf(2.4f32)
+    }
+}
+
+/// Scales `color` so its luminance `l` becomes `curve(l)`, keeping its hue.
+fn scale_luminance(color: Vector3, curve: impl Fn(f32) -> f32) -> Vector3 {
+    let l = luminance(color);
+    if l <= 0f32 {
+        return Vector3::zero();
+    }
+    color * (curve(l) / l)
+}
+
+/// Linear white point of the Hable curve.
+const HABLE_WHITE: f32 = 11.2f32;
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15f32, 0.50f32, 0.10f32, 0.20f32, 0.02f32, 0.30f32);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+/// sRGB to the ACES fit's working space, with the RRT's saturation tweak.
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719f32, 0.35458f32, 0.04823f32],
+    [0.07600f32, 0.90834f32, 0.01566f32],
+    [0.02840f32, 0.13383f32, 0.83777f32],
+];
+
+/// Back from the fit's working space to linear sRGB.
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475f32, -0.53108f32, -0.07367f32],
+    [-0.10208f32, 1.10813f32, -0.00605f32],
+    [-0.00327f32, -0.07276f32, 1.07602f32],
+];
+
+fn rrt_odt_fit(v: f32) -> f32 {
+    let a = v * (v + 0.0245786f32) - 0.000090537f32;
+    let b = v * (0.983729f32 * v + 0.432951f32) + 0.238081f32;
+    a / b
+}
+
+fn mul3(m: &[[f32; 3]; 3], v: Vector3) -> Vector3 {
+    Vector3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+/// Noise in (-1, 1) with a triangular distribution, one quantization step
+/// wide on either side, hashed from the pixel and channel.
+fn triangular_noise(x: usize, y: usize, channel: usize) -> f32 {
+    let seed = (x as u32)
+        .wrapping_mul(0x8da6b343)
+        .wrapping_add((y as u32).wrapping_mul(0xd8163841))
+        .wrapping_add((channel as u32).wrapping_mul(0xcb1ab31f));
+    let (a, b) = (hash(seed), hash(seed ^ 0x5bd1e995));
+    a + b - 1f32
+}
+
+/// Uniform value in [0, 1) from a 32 bit integer hash.
+fn hash(mut h: u32) -> f32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^= h >> 16;
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
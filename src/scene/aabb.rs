@@ -41,18 +41,40 @@ impl AABB {
     }
 
     pub fn from_tris(tris: &[Triangle]) -> AABB {
-        let mut aabb = AABB::new();
+        AABB::from_points(tris.iter().flat_map(|t| t.verts)).unwrap_or_else(AABB::new)
+    }
+
+    /// Smallest box around `points`, `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vector3>) -> Option<AABB> {
+        points
+            .into_iter()
+            .fold(None, |aabb: Option<AABB>, p| match aabb {
+                Some(aabb) => Some(AABB::from_bounds(aabb.min.min(p), aabb.max.max(p))),
+                None => Some(AABB::from_bounds(p, p)),
+            })
+    }
+
+    /// Smallest box holding both boxes.
+    pub fn union(&self, other: &AABB) -> AABB {
+        AABB::from_bounds(self.min.min(other.min), self.max.max(other.max))
+    }
 
-        tris.iter().for_each(|t| {
-            aabb.include(t.verts[0]);
-            aabb.include(t.verts[1]);
-            aabb.include(t.verts[2]);
-        });
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2f32 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
 
-        aabb
+    pub fn center(&self) -> Vector3 {
+        (self.min + self.max) * 0.5f32
     }
 
     pub fn intersect(&self, ray: &Ray) -> bool {
+        self.entry(ray).is_some()
+    }
+
+    /// How far along `ray`, in multiples of its direction, it enters the box.
+    /// Zero if it starts inside, `None` if it misses.
+    pub fn entry(&self, ray: &Ray) -> Option<f32> {
         let tx1 = (self.min.x - ray.origin.x) / ray.direction.x;
         let tx2 = (self.max.x - ray.origin.x) / ray.direction.x;
         let mut tmin = tx1.min(tx2);
@@ -70,6 +92,6 @@ impl AABB {
         tmin = tmin.max(tz1.min(tz2));
         tmax = tmax.min(tz1.max(tz2));
 
-        tmax >= tmin && tmin < 1e30f32 && tmax > 0f32
+        (tmax >= tmin && tmin < 1e30f32 && tmax > 0f32).then(|| tmin.max(0f32))
     }
 }
@@ -1,236 +1,866 @@
 use crate::{math::Transform, rendering::Ray};
 
-use super::{HitData, Triangle, AABB};
+use raylib::math::Vector3;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::time::{Duration, Instant};
 
+use super::{triangle::TriangleHitData, HitData, Triangle, AABB};
+
+/// Deepest a tree is built, so traversal fits in a fixed size stack. Nodes
+/// this deep stay leaves however many triangles they hold.
+const MAX_DEPTH: usize = 64;
+/// Nodes with at least this many triangles are binned in parallel and have
+/// their subtrees built on separate threads. Smaller ones aren't worth it.
+const PARALLEL_BUILD_SIZE: usize = 4096;
+
+/// A node of the flattened tree, 32 bytes so two fit in a cache line. Nodes
+/// are stored depth first, so an inner node's left child directly follows it.
+#[derive(Clone)]
 pub struct BVHNode {
     aabb: AABB,
-    left: usize,
-    right: usize,
-    leaf: bool,
-    first: usize,
-    tris: usize,
+    /// Right child of an inner node, first triangle of a leaf.
+    offset: u32,
+    /// Number of triangles in a leaf, zero for inner nodes.
+    count: u32,
 }
 
-impl Clone for BVHNode {
-    fn clone(&self) -> Self {
-        BVHNode {
-            aabb: self.aabb.clone(),
-            right: self.right,
-            left: self.left,
-            leaf: self.leaf,
-            first: self.first,
-            tris: self.tris,
-        }
-    }
-}
+const _: () = assert!(std::mem::size_of::<BVHNode>() == 32);
 
 pub struct BVH {
-    nodes: Vec<Option<BVHNode>>,
+    nodes: Vec<BVHNode>,
     tris: Vec<Triangle>,
-    used_nodes: usize,
 }
 
 impl BVH {
     pub fn new(tris: Vec<Triangle>) -> Self {
         Self {
-            nodes: (0..(2 * tris.len() - 1)).map(|_| None).collect(),
+            nodes: Vec::new(),
             tris,
-            used_nodes: 0,
         }
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<HitData> {
-        self.intersect_node(ray, 0, 0)
+    /// The triangles in the order the built tree refers to them.
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.tris
     }
 
-    fn intersect_node(&self, ray: &Ray, idx: usize, hits: u32) -> Option<HitData> {
-        if let Some(node) = &self.nodes[idx] {
-            if !node.aabb.intersect(ray) {
-                return None;
+    /// Finds the closest triangle `ray` hits. Children are visited nearer
+    /// first, and nodes the ray enters behind the closest hit so far are
+    /// skipped.
+    pub fn intersect(&self, ray: &Ray) -> Option<HitData> {
+        let root_entry = self.nodes.first()?.aabb.entry(ray)?;
+
+        // box entries are in multiples of the direction, hits in world units
+        let scale = ray.direction.length();
+        let mut closest: Option<TriangleHitData> = None;
+        let mut max_dist = f32::INFINITY;
+        let mut visited = 0;
+
+        let mut stack = [(0u32, 0f32); MAX_DEPTH];
+        stack[0] = (0, root_entry);
+        let mut len = 1;
+
+        while len > 0 {
+            len -= 1;
+            let (idx, entry) = stack[len];
+            if entry * scale > max_dist {
+                continue;
             }
+            visited += 1;
 
-            if node.leaf {
-                let tris = &self.tris[node.first..(node.first + node.tris)];
-
-                if let Some((_, hit)) = tris.iter().fold(None, |acc, tri| match acc {
-                    None => {
-                        if let Some(hit) = tri.intersect(ray) {
-                            Some((
-                                ray.origin.distance_to(hit.p),
-                                HitData {
-                                    position: hit.p,
-                                    normal: hit.normal,
-                                    bary: hit.bary,
-                                    node_hits: hits,
-                                },
-                            ))
-                        } else {
-                            None
-                        }
-                    }
-                    Some((d, c_hit)) => {
-                        if let Some(hit) = tri.intersect(ray) {
-                            let nd = ray.origin.distance_to(hit.p);
-                            if nd < d {
-                                Some((
-                                    nd,
-                                    HitData {
-                                        position: hit.p,
-                                        normal: hit.normal,
-                                        bary: hit.bary,
-                                        node_hits: hits,
-                                    },
-                                ))
-                            } else {
-                                Some((d, c_hit))
-                            }
-                        } else {
-                            Some((d, c_hit))
+            let node = &self.nodes[idx as usize];
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for tri in &self.tris[first..first + node.count as usize] {
+                    if let Some(hit) = tri.intersect(ray) {
+                        let dist = ray.origin.distance_to(hit.p);
+                        if dist < max_dist {
+                            max_dist = dist;
+                            closest = Some(hit);
                         }
                     }
-                }) {
-                    return Some(hit);
                 }
+                continue;
+            }
+
+            let children = [idx + 1, node.offset].map(|child| {
+                self.nodes[child as usize]
+                    .aabb
+                    .entry(ray)
+                    .map(|t| (child, t))
+            });
+            let (near, far) = match children {
+                [Some(a), Some(b)] if b.1 < a.1 => (Some(b), Some(a)),
+                [a, b] => (a, b),
+            };
+            // the nearer child goes on top so its hits can prune the other
+            for (child, entry) in [far, near].into_iter().flatten() {
+                stack[len] = (child, entry);
+                len += 1;
+            }
+        }
+
+        closest.map(|hit| HitData {
+            position: hit.p,
+            normal: hit.normal,
+            geometric_normal: hit.geometric_normal,
+            bary: hit.bary,
+            uv: hit.uv,
+            node_hits: visited,
+            front_face: true,
+            object_id: 0,
+            material_id: 0,
+        })
+    }
+
+    /// Builds the tree with the default builder.
+    pub fn build(&mut self) -> Duration {
+        self.build_with(BVHBuilder::default())
+    }
+
+    /// Builds the tree, replacing any built before, and returns how long that
+    /// took. Large meshes are split up over rayon's threads, which doesn't
+    /// change the tree.
+    pub fn build_with(&mut self, builder: BVHBuilder) -> Duration {
+        let start = Instant::now();
+        self.nodes.clear();
+        if self.tris.is_empty() {
+            return start.elapsed();
+        }
+
+        let mut nodes = Vec::with_capacity(2 * self.tris.len() - 1);
+        build_node(&mut nodes, &mut self.tris, 0, 0, &builder);
+        self.nodes = nodes;
+        start.elapsed()
+    }
+
+    /// Measures the built tree, pricing it with the costs in `settings`.
+    pub fn stats(&self, settings: &SahSettings) -> BVHStats {
+        let mut stats = BVHStats::default();
+        let Some(root) = self.nodes.first() else {
+            return stats;
+        };
+
+        let root_area = root.aabb.surface_area().max(f32::MIN_POSITIVE);
+        let mut stack = vec![(0usize, 1usize)];
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx];
+            let area = node.aabb.surface_area() / root_area;
+
+            stats.nodes += 1;
+            stats.depth = stats.depth.max(depth);
+            if node.count > 0 {
+                stats.leaves += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(node.count as usize);
+                stats.sah_cost += settings.intersection_cost * node.count as f32 * area;
             } else {
-                let left = self.intersect_node(ray, node.left, hits + 1);
-                let right = self.intersect_node(ray, node.right, hits + 1);
-
-                return match (left, right) {
-                    (None, None) => None,
-                    (Some(h), None) => Some(h),
-                    (None, Some(h)) => Some(h),
-                    (Some(h1), Some(h2)) => {
-                        let d1 = h1.position.distance_to(ray.origin);
-                        let d2 = h2.position.distance_to(ray.origin);
-
-                        if d1 < d2 {
-                            Some(h1)
-                        } else {
-                            Some(h2)
-                        }
-                    }
-                };
+                stats.sah_cost += settings.traversal_cost * area;
+                stack.push((idx + 1, depth + 1));
+                stack.push((node.offset as usize, depth + 1));
             }
         }
 
+        stats
+    }
+}
+
+/// Appends the subtree over `tris` to `nodes`. `first` is the index of the
+/// first of `tris` in the whole mesh, which leaves refer to.
+fn build_node(
+    nodes: &mut Vec<BVHNode>,
+    tris: &mut [Triangle],
+    first: usize,
+    depth: usize,
+    builder: &BVHBuilder,
+) {
+    if let BVHBuilder::Linear(settings) = builder {
+        if depth >= settings.sah_levels {
+            build_linear(nodes, tris, first, depth, settings);
+            return;
+        }
+    }
+
+    let count = tris.len();
+    let aabb = bounds(tris, |tri| tri.verts).unwrap_or_else(AABB::new);
+
+    let left_count = if depth + 1 < MAX_DEPTH {
+        match builder {
+            BVHBuilder::Midpoint => split_midpoint(tris, &aabb),
+            BVHBuilder::Sah(settings) => split_sah(tris, &aabb, settings),
+            BVHBuilder::Linear(_) => split_sah(tris, &aabb, &SahSettings::default()),
+        }
+    } else {
         None
+    };
+
+    let Some(left_count) = left_count.filter(|&n| n > 0 && n < count) else {
+        push_leaf(nodes, aabb, first, count);
+        return;
+    };
+    let (left, right) = tris.split_at_mut(left_count);
+    let right_first = first + left_count;
+
+    push_inner(
+        nodes,
+        count >= PARALLEL_BUILD_SIZE,
+        |nodes| build_node(nodes, left, first, depth + 1, builder),
+        |nodes| build_node(nodes, right, right_first, depth + 1, builder),
+    );
+}
+
+/// Sorts `tris` along a Morton curve through their centroids with a radix
+/// sort and appends the subtree `build_morton` makes of them.
+fn build_linear(
+    nodes: &mut Vec<BVHNode>,
+    tris: &mut [Triangle],
+    first: usize,
+    depth: usize,
+    settings: &LinearSettings,
+) {
+    let grid = bounds(tris, |tri| [tri.centroid()]).unwrap_or_else(AABB::new);
+    let code = |(i, tri): (usize, &Triangle)| (morton_code(tri.centroid(), &grid), i);
+
+    let keys: Vec<(u32, usize)> = if tris.len() >= PARALLEL_BUILD_SIZE {
+        tris.par_iter().enumerate().map(code).collect()
+    } else {
+        tris.iter().enumerate().map(code).collect()
+    };
+    let keys = radix_sort(keys);
+
+    let sorted: Vec<Triangle> = keys.iter().map(|&(_, i)| tris[i].clone()).collect();
+    tris.clone_from_slice(&sorted);
+    let codes: Vec<u32> = keys.into_iter().map(|(code, _)| code).collect();
+
+    build_morton(nodes, tris, &codes, first, depth, settings.max_leaf_size);
+}
+
+/// Appends the subtree over `tris`, sorted by their Morton `codes`. Every node
+/// splits where the highest bit that differs among its codes flips, found by
+/// `split_index` without looking at every triangle, and boxes are gathered
+/// from the leaves up, so the whole tree takes linear time.
+fn build_morton(
+    nodes: &mut Vec<BVHNode>,
+    tris: &mut [Triangle],
+    codes: &[u32],
+    first: usize,
+    depth: usize,
+    max_leaf_size: usize,
+) {
+    let count = tris.len();
+    if count <= max_leaf_size.max(1) || depth + 1 >= MAX_DEPTH {
+        let aabb = bounds(tris, |tri| tri.verts).unwrap_or_else(AABB::new);
+        push_leaf(nodes, aabb, first, count);
+        return;
     }
 
-    pub fn build(&mut self) {
-        let aabb = AABB::from_tris(&self.tris);
+    let diff = codes[0] ^ codes[count - 1];
+    let left_count = if diff == 0 {
+        // centroids in the same grid cell, any split is as good
+        count / 2
+    } else {
+        split_index(codes, 1 << (31 - diff.leading_zeros()))
+    };
+    let (left, right) = tris.split_at_mut(left_count);
+    let (left_codes, right_codes) = codes.split_at(left_count);
+    let right_first = first + left_count;
 
-        let root = BVHNode {
-            aabb,
-            left: 1,
-            right: 2,
-            leaf: true,
-            first: 0,
-            tris: self.tris.len(),
-        };
+    push_inner(
+        nodes,
+        count >= PARALLEL_BUILD_SIZE,
+        |nodes| build_morton(nodes, left, left_codes, first, depth + 1, max_leaf_size),
+        |nodes| {
+            build_morton(
+                nodes,
+                right,
+                right_codes,
+                right_first,
+                depth + 1,
+                max_leaf_size,
+            )
+        },
+    );
+}
+
+/// Sorts Morton `keys` by their 30 bit codes in three passes of ten bits.
+/// Every pass is stable, so equal codes stay in the order of their indices.
+fn radix_sort(mut keys: Vec<(u32, usize)>) -> Vec<(u32, usize)> {
+    const BITS: u32 = 10;
+    let mut sorted = vec![(0u32, 0usize); keys.len()];
+
+    for pass in 0..3 {
+        let digit = |code: u32| ((code >> (pass * BITS)) & ((1 << BITS) - 1)) as usize;
 
-        self.nodes[0] = Some(root);
-        self.used_nodes += 1;
+        let mut offsets = vec![0usize; 1 << BITS];
+        for &(code, _) in &keys {
+            offsets[digit(code)] += 1;
+        }
+        let mut total = 0;
+        for offset in offsets.iter_mut() {
+            let count = *offset;
+            *offset = total;
+            total += count;
+        }
 
-        self.subdivide(0);
+        for &key in &keys {
+            let offset = &mut offsets[digit(key.0)];
+            sorted[*offset] = key;
+            *offset += 1;
+        }
+        std::mem::swap(&mut keys, &mut sorted);
     }
 
-    fn subdivide(&mut self, node_idx: usize) {
-        if let Some(node) = &mut self.nodes[node_idx] {
-            if node.tris <= 2 {
-                return;
+    keys
+}
+
+/// Index of the first of the sorted `codes` with `bit` set, given that the
+/// first code has it clear and the last has it set. Searches with growing
+/// steps from both ends at once, so it takes time logarithmic in the smaller
+/// side, which adds up to linear time over the tree.
+fn split_index(codes: &[u32], bit: u32) -> usize {
+    let count = codes.len();
+    let clear = |code: &u32| code & bit == 0;
+
+    let mut step = 1;
+    while step < count {
+        if !clear(&codes[step]) {
+            return codes[..step].partition_point(clear);
+        }
+        if clear(&codes[count - 1 - step]) {
+            return count - step + codes[count - step..].partition_point(clear);
+        }
+        step *= 2;
+    }
+    codes.partition_point(clear)
+}
+
+fn push_leaf(nodes: &mut Vec<BVHNode>, aabb: AABB, first: usize, count: usize) {
+    nodes.push(BVHNode {
+        aabb,
+        offset: first as u32,
+        count: count as u32,
+    });
+}
+
+/// Appends an inner node followed by the subtrees `left` and `right` append,
+/// its box around theirs. With `parallel` the right subtree is built on
+/// another thread into a list of its own and moved behind the left one, so
+/// the layout is the same as when built in order.
+fn push_inner(
+    nodes: &mut Vec<BVHNode>,
+    parallel: bool,
+    left: impl FnOnce(&mut Vec<BVHNode>) + Send,
+    right: impl FnOnce(&mut Vec<BVHNode>) + Send,
+) {
+    let idx = nodes.len();
+    nodes.push(BVHNode {
+        aabb: AABB::new(),
+        offset: 0,
+        count: 0,
+    });
+
+    let right_idx = if parallel {
+        let ((), right_nodes) = rayon::join(
+            || left(nodes),
+            || {
+                let mut right_nodes = Vec::new();
+                right(&mut right_nodes);
+                right_nodes
+            },
+        );
+
+        let right_idx = nodes.len();
+        nodes.extend(right_nodes.into_iter().map(|mut node| {
+            if node.count == 0 {
+                node.offset += right_idx as u32;
             }
-            let extent = node.aabb.max - node.aabb.min;
+            node
+        }));
+        right_idx
+    } else {
+        left(nodes);
+        let right_idx = nodes.len();
+        right(nodes);
+        right_idx
+    };
+
+    let aabb = nodes[idx + 1].aabb.union(&nodes[right_idx].aabb);
+    let node = &mut nodes[idx];
+    node.aabb = aabb;
+    node.offset = right_idx as u32;
+}
+
+/// Partitions `tris` around the middle of the longest axis of their box,
+/// returning how many went left. `None` keeps them in a leaf.
+fn split_midpoint(tris: &mut [Triangle], aabb: &AABB) -> Option<usize> {
+    if tris.len() <= 2 {
+        return None;
+    }
+
+    let extent = aabb.max - aabb.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let pos = axis_value(aabb.min, axis) + 0.5 * axis_value(extent, axis);
+
+    Some(partition(tris, |tri| {
+        axis_value(tri.centroid(), axis) < pos
+    }))
+}
+
+/// Partitions `tris` at the cheapest binned SAH split, returning how many went
+/// left. `None` keeps them in a leaf.
+fn split_sah(tris: &mut [Triangle], aabb: &AABB, settings: &SahSettings) -> Option<usize> {
+    let count = tris.len();
+    if count <= 1 {
+        return None;
+    }
+
+    let leaf_cost = settings.intersection_cost * count as f32;
+    let (axis, split, cost) = best_sah_split(tris, aabb.surface_area(), settings)?;
+    if count <= settings.max_leaf_size && cost >= leaf_cost {
+        return None;
+    }
+
+    Some(partition(tris, |tri| split.bin(tri, axis) < split.index))
+}
+
+/// Moves the triangles `left` holds for to the front, returning how many
+/// there are.
+fn partition(tris: &mut [Triangle], left: impl Fn(&Triangle) -> bool) -> usize {
+    let mut left_count = 0;
+    for i in 0..tris.len() {
+        if left(&tris[i]) {
+            tris.swap(left_count, i);
+            left_count += 1;
+        }
+    }
+    left_count
+}
+
+/// How `BVH::build_with` decides where to split a node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BVHBuilder {
+    /// Halves the longest axis of the node's box, down to two triangles per
+    /// leaf. Quick, but uneven meshes end up with large overlapping nodes.
+    Midpoint,
+    /// Picks the split with the lowest surface area heuristic cost among
+    /// evenly spaced candidates.
+    Sah(SahSettings),
+    /// Sorts the triangles along a Morton curve and splits them where their
+    /// codes do, an LBVH. Much faster to build than `Sah` for meshes that are
+    /// rebuilt often, at the cost of slower rays.
+    Linear(LinearSettings),
+}
+
+impl Default for BVHBuilder {
+    fn default() -> Self {
+        BVHBuilder::Sah(SahSettings::default())
+    }
+}
 
-            enum Axis {
-                X,
-                Y,
-                Z,
+/// Parameters of the binned surface area heuristic. A node is worth
+/// splitting when `traversal_cost` plus the expected cost of intersecting the
+/// triangles of the children a ray hits beats intersecting all of its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SahSettings {
+    /// Candidate split positions per axis are the borders between this many
+    /// equally wide bins of triangle centroids.
+    pub bins: usize,
+    /// Nodes with more triangles are always split where possible.
+    pub max_leaf_size: usize,
+    /// Cost of testing a ray against a node's box.
+    pub traversal_cost: f32,
+    /// Cost of testing a ray against one triangle.
+    pub intersection_cost: f32,
+}
+
+impl Default for SahSettings {
+    fn default() -> Self {
+        SahSettings {
+            bins: 16,
+            max_leaf_size: 8,
+            traversal_cost: 1f32,
+            intersection_cost: 2f32,
+        }
+    }
+}
+
+/// Parameters of the linear builder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearSettings {
+    /// Nodes with more triangles are always split.
+    pub max_leaf_size: usize,
+    /// Levels at the top of the tree split with the default SAH settings
+    /// before switching to Morton codes, as in HLBVH. The few large nodes
+    /// there matter most to how fast rays are and are cheap to split well.
+    pub sah_levels: usize,
+}
+
+impl Default for LinearSettings {
+    fn default() -> Self {
+        LinearSettings {
+            max_leaf_size: 4,
+            sah_levels: 0,
+        }
+    }
+}
+
+/// Shape of a built tree, see `BVH::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Nodes on the longest path from the root to a leaf.
+    pub depth: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of a ray through the tree: every node's traversal or
+    /// intersection cost weighted by its surface area relative to the root's.
+    pub sah_cost: f32,
+}
+
+/// A split between two of the bins the centroids along one axis fall into.
+struct BinSplit {
+    min: f32,
+    /// Bins per unit of length.
+    scale: f32,
+    bins: usize,
+    /// Bins below this go left.
+    index: usize,
+}
+
+impl BinSplit {
+    fn bin(&self, tri: &Triangle, axis: usize) -> usize {
+        let centroid = axis_value(tri.centroid(), axis);
+        (((centroid - self.min) * self.scale) as usize).min(self.bins - 1)
+    }
+}
+
+/// Triangle counts and bounds of the bins along each axis.
+struct Bins {
+    counts: [Vec<usize>; 3],
+    bounds: [Vec<Option<AABB>>; 3],
+}
+
+impl Bins {
+    fn new(bins: usize) -> Self {
+        Bins {
+            counts: [(); 3].map(|_| vec![0; bins]),
+            bounds: [(); 3].map(|_| vec![None; bins]),
+        }
+    }
+
+    fn add(mut self, tri: &Triangle, splits: &[Option<BinSplit>; 3]) -> Self {
+        let tri_bounds = AABB::from_tris(std::slice::from_ref(tri));
+        for (axis, split) in splits.iter().enumerate() {
+            if let Some(split) = split {
+                let bin = split.bin(tri, axis);
+                self.counts[axis][bin] += 1;
+                self.bounds[axis][bin] = Some(match self.bounds[axis][bin].take() {
+                    Some(aabb) => aabb.union(&tri_bounds),
+                    None => tri_bounds.clone(),
+                });
             }
+        }
+        self
+    }
 
-            let split: (Axis, f32) = if extent.x >= extent.y && extent.x >= extent.z {
-                (Axis::X, node.aabb.min.x + 0.5 * extent.x)
-            } else if extent.y >= extent.x && extent.y >= extent.z {
-                (Axis::Y, node.aabb.min.y + 0.5 * extent.y)
-            } else {
-                (Axis::Z, node.aabb.min.z + 0.5 * extent.z)
-            };
+    /// Adds up bins filled from different triangles. Unions and sums don't
+    /// depend on the order, so neither does the result.
+    fn merge(mut self, other: Bins) -> Self {
+        for axis in 0..3 {
+            for (count, other) in self.counts[axis].iter_mut().zip(&other.counts[axis]) {
+                *count += other;
+            }
+            for (aabb, other) in self.bounds[axis].iter_mut().zip(&other.bounds[axis]) {
+                *aabb = union(aabb.take(), other);
+            }
+        }
+        self
+    }
+}
 
-            let mut i = node.first;
-            let mut j = i + node.tris - 1;
-
-            while i <= j && j < self.tris.len() && i < self.tris.len() {
-                match split {
-                    (Axis::X, pos) => {
-                        if self.tris[i].centroid().x < pos {
-                            i += 1;
-                        } else {
-                            let tmp = self.tris[i].clone();
-                            self.tris[i] = self.tris[j].clone();
-                            self.tris[j] = tmp;
-                            j -= 1;
-                        }
-                    }
-                    (Axis::Y, pos) => {
-                        if self.tris[i].centroid().y < pos {
-                            i += 1;
-                        } else {
-                            let tmp = self.tris[i].clone();
-                            self.tris[i] = self.tris[j].clone();
-                            self.tris[j] = tmp;
-                            j -= 1;
-                        }
-                    }
-                    (Axis::Z, pos) => {
-                        if self.tris[i].centroid().z < pos {
-                            i += 1;
-                        } else {
-                            let tmp = self.tris[i].clone();
-                            self.tris[i] = self.tris[j].clone();
-                            self.tris[j] = tmp;
-                            j -= 1;
-                        }
-                    }
-                }
+/// The cheapest split of `tris` over all axes as the axis, the split and its
+/// cost, or `None` if their centroids all coincide. Large nodes are binned in
+/// parallel.
+fn best_sah_split(
+    tris: &[Triangle],
+    node_area: f32,
+    settings: &SahSettings,
+) -> Option<(usize, BinSplit, f32)> {
+    let centroids = bounds(tris, |tri| [tri.centroid()])?;
+    let bins = settings.bins.max(2);
+
+    let splits = [0, 1, 2].map(|axis| {
+        let min = axis_value(centroids.min, axis);
+        let extent = axis_value(centroids.max, axis) - min;
+        (extent > 0f32).then(|| BinSplit {
+            min,
+            scale: bins as f32 / extent,
+            bins,
+            index: 0,
+        })
+    });
+    if splits.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let binned = if tris.len() >= PARALLEL_BUILD_SIZE {
+        tris.par_iter()
+            .fold(|| Bins::new(bins), |binned, tri| binned.add(tri, &splits))
+            .reduce(|| Bins::new(bins), Bins::merge)
+    } else {
+        tris.iter()
+            .fold(Bins::new(bins), |binned, tri| binned.add(tri, &splits))
+    };
+
+    let mut best: Option<(usize, BinSplit, f32)> = None;
+    for (axis, split) in splits.iter().enumerate() {
+        let Some(split) = split else {
+            continue;
+        };
+        let counts = &binned.counts[axis];
+        let bounds = &binned.bounds[axis];
+
+        // areas and counts of everything right of each border, swept from the end
+        let mut right_area = vec![0f32; bins];
+        let mut right_count = vec![0usize; bins];
+        let mut sweep: Option<AABB> = None;
+        let mut count = 0;
+        for i in (1..bins).rev() {
+            sweep = union(sweep, &bounds[i]);
+            count += counts[i];
+            right_area[i] = sweep.as_ref().map_or(0f32, AABB::surface_area);
+            right_count[i] = count;
+        }
+
+        let mut sweep: Option<AABB> = None;
+        let mut count = 0;
+        for i in 1..bins {
+            sweep = union(sweep, &bounds[i - 1]);
+            count += counts[i - 1];
+            if count == 0 || right_count[i] == 0 {
+                continue;
             }
 
-            let left_count = i - node.first;
-            if left_count == 0 || left_count == node.tris {
-                return;
+            let left_area = sweep.as_ref().map_or(0f32, AABB::surface_area);
+            let cost = settings.traversal_cost
+                + settings.intersection_cost
+                    * (left_area * count as f32 + right_area[i] * right_count[i] as f32)
+                    / node_area.max(f32::MIN_POSITIVE);
+
+            if best.as_ref().is_none_or(|(_, _, best)| cost < *best) {
+                best = Some((axis, BinSplit { index: i, ..*split }, cost));
             }
+        }
+    }
 
-            node.leaf = false;
+    best
+}
 
-            let left_idx = self.used_nodes;
-            let right_idx = self.used_nodes + 1;
+/// Smallest box around the `points` of every triangle, gathered in parallel
+/// for large slices. `None` if there are none.
+fn bounds<const N: usize>(
+    tris: &[Triangle],
+    points: impl Fn(&Triangle) -> [Vector3; N] + Sync,
+) -> Option<AABB> {
+    if tris.len() >= PARALLEL_BUILD_SIZE {
+        tris.par_iter()
+            .map(|tri| AABB::from_points(points(tri)))
+            .reduce(|| None, |a, b| union(a, &b))
+    } else {
+        AABB::from_points(tris.iter().flat_map(points))
+    }
+}
 
-            self.used_nodes += 2;
+/// Quantizes `p` to 10 bits per axis of `grid` and interleaves the bits, so
+/// points close along the resulting curve are close in space.
+fn morton_code(p: Vector3, grid: &AABB) -> u32 {
+    let quantize = |axis: usize| {
+        let min = axis_value(grid.min, axis);
+        let extent = axis_value(grid.max, axis) - min;
+        if extent > 0f32 {
+            ((axis_value(p, axis) - min) / extent * 1023f32).clamp(0f32, 1023f32) as u32
+        } else {
+            0
+        }
+    };
+    (spread_bits(quantize(0)) << 2) | (spread_bits(quantize(1)) << 1) | spread_bits(quantize(2))
+}
 
-            let left = BVHNode {
-                left: 0,
-                right: 0,
-                aabb: AABB::from_tris(&self.tris[node.first..(node.first + left_count)]),
-                leaf: true,
-                first: node.first,
-                tris: left_count,
-            };
+/// Moves the lower 10 bits of `v` to every third bit.
+fn spread_bits(v: u32) -> u32 {
+    let mut v = v & 0x3ff;
+    v = (v | (v << 16)) & 0x030000ff;
+    v = (v | (v << 8)) & 0x0300f00f;
+    v = (v | (v << 4)) & 0x030c30c3;
+    (v | (v << 2)) & 0x09249249
+}
 
-            let right = BVHNode {
-                left: 0,
-                right: 0,
-                aabb: AABB::from_tris(&self.tris[i..(i + node.tris - left_count)]),
-                leaf: true,
-                first: i,
-                tris: node.tris - left_count,
-            };
+fn union(aabb: Option<AABB>, other: &Option<AABB>) -> Option<AABB> {
+    match (aabb, other) {
+        (Some(aabb), Some(other)) => Some(aabb.union(other)),
+        (aabb, None) => aabb,
+        (None, other) => other.clone(),
+    }
+}
+
+fn axis_value(v: Vector3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{rand_f32, rand_vec3, seed_rng};
+
+    /// Small random triangles scattered through a cube, enough of them for
+    /// the top of the tree to be built in parallel.
+    fn random_tris(count: usize) -> Vec<Triangle> {
+        seed_rng(7);
+        (0..count)
+            .map(|_| {
+                let p = rand_vec3() * 10f32;
+                Triangle {
+                    verts: [p, p + rand_vec3() * 0.1f32, p + rand_vec3() * 0.1f32],
+                    normals: None,
+                    uvs: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_does_not_depend_on_thread_count() {
+        let tris = random_tris(4 * PARALLEL_BUILD_SIZE);
+        let pool = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        };
+        let (single, many) = (pool(1), pool(8));
+
+        for builder in [
+            BVHBuilder::Sah(SahSettings::default()),
+            BVHBuilder::Linear(LinearSettings::default()),
+            BVHBuilder::Linear(LinearSettings {
+                sah_levels: 2,
+                ..LinearSettings::default()
+            }),
+        ] {
+            let mut serial = BVH::new(tris.clone());
+            single.install(|| serial.build_with(builder));
+
+            let mut default = BVH::new(tris.clone());
+            default.build_with(builder);
+            let mut parallel = BVH::new(tris.clone());
+            many.install(|| parallel.build_with(builder));
+
+            for other in [&default, &parallel] {
+                assert_eq!(serial.nodes.len(), other.nodes.len(), "{builder:?}");
+                for (a, b) in serial.nodes.iter().zip(&other.nodes) {
+                    assert_eq!(
+                        (a.aabb.min, a.aabb.max, a.offset, a.count),
+                        (b.aabb.min, b.aabb.max, b.offset, b.count),
+                        "{builder:?}"
+                    );
+                }
+                for (a, b) in serial.tris.iter().zip(&other.tris) {
+                    assert_eq!(a.verts, b.verts, "{builder:?}");
+                }
+            }
+        }
+    }
 
-            node.left = left_idx;
-            node.right = right_idx;
+    #[test]
+    fn spread_bits_moves_bits_three_apart() {
+        assert_eq!(spread_bits(0x3ff), 0x09249249);
+        assert_eq!(spread_bits(0b101), 0b1000001);
+        // only the lower 10 bits are kept
+        assert_eq!(spread_bits(0x400), 0);
+    }
+
+    #[test]
+    fn morton_code_puts_x_above_y_above_z() {
+        let grid = AABB::from_bounds(Vector3::zero(), Vector3::one());
+        let code = |x, y, z| morton_code(Vector3::new(x, y, z), &grid);
 
-            self.nodes[left_idx] = Some(left);
-            self.nodes[right_idx] = Some(right);
+        assert_eq!(code(0f32, 0f32, 0f32), 0);
+        assert_eq!(code(1f32, 0f32, 0f32), 0x09249249 << 2);
+        assert_eq!(code(0f32, 1f32, 0f32), 0x09249249 << 1);
+        assert_eq!(code(0f32, 0f32, 1f32), 0x09249249);
+        assert_eq!(code(1f32, 1f32, 1f32), 0x3fffffff);
+        // the top bits of x decide the order before those of y and z
+        assert!(code(0.51f32, 0f32, 0f32) > code(0.49f32, 1f32, 1f32));
+        assert!(code(0f32, 0.51f32, 0f32) > code(0f32, 0.49f32, 1f32));
+    }
 
-            self.subdivide(left_idx);
-            self.subdivide(right_idx);
+    #[test]
+    fn split_index_finds_the_first_set_bit() {
+        for count in 2..40 {
+            for split in 1..count {
+                let codes: Vec<u32> = (0..count).map(|i| (i >= split) as u32 * 8 + 1).collect();
+                assert_eq!(split_index(&codes, 8), split, "{count} codes");
+            }
+        }
+    }
+
+    #[test]
+    fn radix_sort_orders_by_code_then_index() {
+        let keys = vec![
+            (0x3ff00000, 0),
+            (5, 1),
+            (0x3ff00000, 2),
+            (1 << 10, 3),
+            (5, 4),
+        ];
+        let mut expected = keys.clone();
+        expected.sort_unstable();
+        assert_eq!(radix_sort(keys), expected);
+    }
+
+    #[test]
+    fn linear_tree_finds_the_same_hits_as_sah() {
+        let tris = random_tris(2000);
+        let mut sah = BVH::new(tris.clone());
+        sah.build_with(BVHBuilder::Sah(SahSettings::default()));
+
+        let linear_builders = [
+            LinearSettings::default(),
+            LinearSettings {
+                sah_levels: 3,
+                ..LinearSettings::default()
+            },
+        ];
+        for settings in linear_builders {
+            let mut linear = BVH::new(tris.clone());
+            linear.build_with(BVHBuilder::Linear(settings));
+
+            seed_rng(3);
+            let mut hits = 0;
+            for _ in 0..2000 {
+                // aimed at a triangle so most rays hit something
+                let target =
+                    tris[(rand_f32() * tris.len() as f32) as usize % tris.len()].centroid();
+                let origin = rand_vec3() * 20f32;
+                let ray = Ray::new(origin, (target - origin).normalized());
+                let (a, b) = (sah.intersect(&ray), linear.intersect(&ray));
+
+                assert_eq!(a.is_some(), b.is_some(), "{settings:?}");
+                if let (Some(a), Some(b)) = (a, b) {
+                    assert_eq!(a.position, b.position, "{settings:?}");
+                    hits += 1;
+                }
+            }
+            assert!(hits > 1000, "only {hits} rays hit");
         }
     }
 }
@@ -0,0 +1,1017 @@
+use crate::math::Transform;
+use crate::rendering::{
+    Aov, DielectricMaterial, EmissiveMaterial, Exposure, LambertianMaterial, MetalMaterial,
+    Projection, RTMaterial, RayCamera, ToneMapper, ToneMapping, DEFAULT_FOV, NEUTRAL_WHITE_BALANCE,
+};
+use crate::scene::bvh::{BVHBuilder, LinearSettings, SahSettings};
+use crate::scene::environment::{
+    ConstantEnvironment, Environment, EnvironmentMap, GradientEnvironment,
+};
+use crate::scene::light::{DirectionalLight, Light, PointLight, SpotLight};
+use crate::scene::mesh::Mesh;
+use crate::scene::models::{Scene, SceneObject};
+use crate::scene::sky::SkyEnvironment;
+use crate::scene::{Plane, Sphere};
+use image::ImageError;
+use raylib::math::{Matrix, Vector3};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Top level layout of a `.toml` scene file.
+///
+/// ```toml
+/// [camera]
+/// position = [0, 2, 0]
+/// yaw = 90
+///
+/// [render]
+/// samples = 128
+///
+/// [materials.white]
+/// type = "lambertian"
+/// albedo = [0.5, 0.5, 0.5]
+///
+/// [[objects]]
+/// type = "sphere"
+/// center = [0, 0.75, 3]
+/// radius = 0.75
+/// material = "white"
+///
+/// [[lights]]
+/// type = "point"
+/// position = [0, 4, 3]
+/// intensity = [20, 20, 20]
+///
+/// [environment]
+/// type = "map"
+/// path = "studio.hdr"
+/// rotation = 90
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub render: RenderSettings,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub environment: EnvironmentDescription,
+}
+
+/// The view direction is given either by `yaw` and `pitch` or by a `target`
+/// to look at, which takes precedence. `up` and `roll` orient the image.
+/// `fov` is the vertical field of view in degrees, or the angle spanned by the
+/// image circle for a fisheye projection. Leaving out `focus_distance`
+/// focuses on the target, or on whatever is in the center of the image.
+/// Without an `aperture` the lens is opened to match the exposure's
+/// `f_number` if there is one, and is a pinhole otherwise.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "is_world_up")]
+    pub up: [f32; 3],
+    #[serde(skip_serializing_if = "is_zero_f32")]
+    pub roll: f32,
+    pub fov: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+    pub projection: ProjectionDescription,
+    #[serde(skip_serializing_if = "ExposureDescription::is_neutral")]
+    pub exposure: ExposureDescription,
+}
+
+/// Photographic exposure of the camera: `shutter` in seconds, `compensation`
+/// in stops and `white_balance` in kelvin.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExposureDescription {
+    pub shutter: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub f_number: Option<f32>,
+    pub iso: f32,
+    pub compensation: f32,
+    pub white_balance: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProjectionDescription {
+    #[default]
+    Perspective,
+    Orthographic {
+        width: f32,
+    },
+    Fisheye,
+    Equirectangular,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            position: [0f32, 2f32, 0f32],
+            yaw: 90f32,
+            pitch: 0f32,
+            target: None,
+            up: [0f32, 1f32, 0f32],
+            roll: 0f32,
+            fov: DEFAULT_FOV,
+            aperture: None,
+            focus_distance: None,
+            projection: ProjectionDescription::Perspective,
+            exposure: ExposureDescription::default(),
+        }
+    }
+}
+
+impl Default for ExposureDescription {
+    fn default() -> Self {
+        Self {
+            shutter: 1f32,
+            f_number: None,
+            iso: 100f32,
+            compensation: 0f32,
+            white_balance: NEUTRAL_WHITE_BALANCE,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+    pub bounces: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// How 8 and 16 bit output and the viewer compress the rendered radiance.
+    #[serde(skip_serializing_if = "ToneMapperDescription::is_clamp")]
+    pub tone_mapping: ToneMapperDescription,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dither: bool,
+    /// Run the denoiser over the finished image.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub denoise: bool,
+    /// Names of the AOVs to write along with the image, see `Aov::name`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aovs: Vec<String>,
+    /// Add Cryptomatte object and material layers to EXR output.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cryptomatte: bool,
+    /// How the BVHs of meshes are built when the scene is loaded.
+    #[serde(skip_serializing_if = "BVHDescription::is_default")]
+    pub bvh: BVHDescription,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ToneMapperDescription {
+    #[default]
+    Clamp,
+    Reinhard,
+    ExtendedReinhard {
+        white: f32,
+    },
+    Hable,
+    AcesFitted,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 450,
+            samples: 64,
+            bounces: 10,
+            seed: None,
+            tone_mapping: ToneMapperDescription::Clamp,
+            dither: false,
+            denoise: false,
+            aovs: Vec::new(),
+            cryptomatte: false,
+            bvh: BVHDescription::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn tone_mapping(&self) -> ToneMapping {
+        ToneMapping::new(self.tone_mapping.to_tone_mapper(), self.dither)
+    }
+
+    /// The AOVs in `aovs`, skipping unknown names.
+    pub fn aovs(&self) -> Vec<Aov> {
+        self.aovs
+            .iter()
+            .filter_map(|name| Aov::from_name(name))
+            .collect()
+    }
+}
+
+impl ToneMapperDescription {
+    pub fn from_tone_mapper(tone_mapper: ToneMapper) -> Self {
+        match tone_mapper {
+            ToneMapper::Clamp => ToneMapperDescription::Clamp,
+            ToneMapper::Reinhard => ToneMapperDescription::Reinhard,
+            ToneMapper::ExtendedReinhard { white } => {
+                ToneMapperDescription::ExtendedReinhard { white }
+            }
+            ToneMapper::Hable => ToneMapperDescription::Hable,
+            ToneMapper::AcesFitted => ToneMapperDescription::AcesFitted,
+        }
+    }
+
+    pub fn to_tone_mapper(&self) -> ToneMapper {
+        match *self {
+            ToneMapperDescription::Clamp => ToneMapper::Clamp,
+            ToneMapperDescription::Reinhard => ToneMapper::Reinhard,
+            ToneMapperDescription::ExtendedReinhard { white } => {
+                ToneMapper::ExtendedReinhard { white }
+            }
+            ToneMapperDescription::Hable => ToneMapper::Hable,
+            ToneMapperDescription::AcesFitted => ToneMapper::AcesFitted,
+        }
+    }
+
+    fn is_clamp(&self) -> bool {
+        *self == ToneMapperDescription::Clamp
+    }
+}
+
+/// A `BVHBuilder`. Parameters left out take their default values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BVHDescription {
+    Midpoint,
+    Sah {
+        #[serde(default = "default_bins")]
+        bins: usize,
+        #[serde(default = "default_max_leaf_size")]
+        max_leaf_size: usize,
+        #[serde(default = "default_traversal_cost")]
+        traversal_cost: f32,
+        #[serde(default = "default_intersection_cost")]
+        intersection_cost: f32,
+    },
+    Linear {
+        #[serde(default = "default_linear_max_leaf_size")]
+        max_leaf_size: usize,
+        #[serde(default = "default_sah_levels")]
+        sah_levels: usize,
+    },
+}
+
+impl Default for BVHDescription {
+    fn default() -> Self {
+        BVHDescription::from_builder(BVHBuilder::default())
+    }
+}
+
+impl BVHDescription {
+    pub fn from_builder(builder: BVHBuilder) -> Self {
+        match builder {
+            BVHBuilder::Midpoint => BVHDescription::Midpoint,
+            BVHBuilder::Sah(settings) => BVHDescription::Sah {
+                bins: settings.bins,
+                max_leaf_size: settings.max_leaf_size,
+                traversal_cost: settings.traversal_cost,
+                intersection_cost: settings.intersection_cost,
+            },
+            BVHBuilder::Linear(settings) => BVHDescription::Linear {
+                max_leaf_size: settings.max_leaf_size,
+                sah_levels: settings.sah_levels,
+            },
+        }
+    }
+
+    pub fn to_builder(&self) -> BVHBuilder {
+        match *self {
+            BVHDescription::Midpoint => BVHBuilder::Midpoint,
+            BVHDescription::Sah {
+                bins,
+                max_leaf_size,
+                traversal_cost,
+                intersection_cost,
+            } => BVHBuilder::Sah(SahSettings {
+                bins,
+                max_leaf_size,
+                traversal_cost,
+                intersection_cost,
+            }),
+            BVHDescription::Linear {
+                max_leaf_size,
+                sah_levels,
+            } => BVHBuilder::Linear(LinearSettings {
+                max_leaf_size,
+                sah_levels,
+            }),
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == BVHDescription::default()
+    }
+}
+
+/// A metal's `roughness` is perceptual and gets squared into the GGX alpha,
+/// so scenes written for the old fuzzy reflections keep their look.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian { albedo: [f32; 3] },
+    Metal { albedo: [f32; 3], roughness: f32 },
+    Emissive { emit: [f32; 3] },
+    Dielectric { ior: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+    },
+    Plane {
+        position: [f32; 3],
+        normal: [f32; 3],
+        material: String,
+    },
+    Mesh {
+        path: String,
+        material: String,
+        #[serde(default)]
+        transform: TransformDescription,
+        /// Overrides `[render] bvh` for this mesh, e.g. a quicker to rebuild
+        /// `linear` tree for one that gets edited.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bvh: Option<BVHDescription>,
+    },
+}
+
+/// Analytic lights, see `scene::light`. Angles are in degrees.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDescription {
+    Point {
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        intensity: [f32; 3],
+        angle: f32,
+        #[serde(default)]
+        falloff: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        irradiance: [f32; 3],
+    },
+}
+
+/// What rays that miss every object see. Map paths are relative to the scene
+/// file and `rotation` turns the map around the y axis, in degrees.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EnvironmentDescription {
+    Constant {
+        color: [f32; 3],
+    },
+    #[default]
+    Gradient,
+    Map {
+        path: String,
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+    /// Preetham daylight with a sun disk, see `SkyEnvironment`.
+    Sky {
+        sun_elevation: f32,
+        #[serde(default)]
+        sun_azimuth: f32,
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+/// Scale, then rotate (euler angles in degrees), then translate. A raw
+/// column major `matrix` can be given instead, which is what transforms
+/// built in code are saved as.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformDescription {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<[f32; 16]>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub translate: [f32; 3],
+    #[serde(skip_serializing_if = "is_zero")]
+    pub rotate: [f32; 3],
+    #[serde(skip_serializing_if = "is_one")]
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        Self {
+            matrix: None,
+            translate: [0f32; 3],
+            rotate: [0f32; 3],
+            scale: [1f32; 3],
+        }
+    }
+}
+
+impl TransformDescription {
+    pub fn from_matrix(m: Matrix) -> Self {
+        Self {
+            matrix: Some(m.to_array()),
+            ..Self::default()
+        }
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        if let Some(m) = self.matrix {
+            return Transform::new(Matrix {
+                m0: m[0],
+                m1: m[1],
+                m2: m[2],
+                m3: m[3],
+                m4: m[4],
+                m5: m[5],
+                m6: m[6],
+                m7: m[7],
+                m8: m[8],
+                m9: m[9],
+                m10: m[10],
+                m11: m[11],
+                m12: m[12],
+                m13: m[13],
+                m14: m[14],
+                m15: m[15],
+            });
+        }
+
+        let [sx, sy, sz] = self.scale;
+        let [tx, ty, tz] = self.translate;
+        let [rx, ry, rz] = self.rotate.map(|a| a.to_radians());
+
+        Transform::new(
+            Matrix::scale(sx, sy, sz)
+                * Matrix::rotate_xyz(Vector3::new(rx, ry, rz))
+                * Matrix::translate(tx, ty, tz),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Serialize(toml::ser::Error),
+    Image(PathBuf, ImageError),
+    UnknownMaterial { object: usize, material: String },
+    Mesh { object: usize, reason: String },
+    UnknownAov(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, err) => write!(f, "failed to read {}: {err}", path.display()),
+            SceneError::Parse(path, err) => write!(f, "in {}: {err}", path.display()),
+            SceneError::Image(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            SceneError::Serialize(err) => write!(f, "failed to serialize scene: {err}"),
+            SceneError::UnknownMaterial { object, material } => write!(
+                f,
+                "objects[{object}].material: no material named `{material}` in [materials]"
+            ),
+            SceneError::Mesh { object, reason } => write!(f, "objects[{object}].path: {reason}"),
+            SceneError::UnknownAov(name) => write!(f, "render.aovs: unknown AOV `{name}`"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// What `Scene::from_file_with_report` did besides building the scene, for
+/// callers that want to show it.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Every mesh loaded, in object order.
+    pub meshes: Vec<MeshReport>,
+}
+
+#[derive(Debug)]
+pub struct MeshReport {
+    /// Index of the mesh in `objects`.
+    pub object: usize,
+    pub path: PathBuf,
+    pub triangles: usize,
+    pub build_time: Duration,
+}
+
+impl Scene {
+    /// Loads a scene file. Relative mesh paths are resolved against the
+    /// directory containing the scene file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+        Scene::from_file_with_report(path).map(|(scene, _)| scene)
+    }
+
+    /// Like `from_file`, also returning what went into loading the scene.
+    pub fn from_file_with_report<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Scene, LoadReport), SceneError> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path).map_err(|err| SceneError::Io(path.into(), err))?;
+        let desc: SceneDescription =
+            toml::from_str(&src).map_err(|err| SceneError::Parse(path.into(), err))?;
+
+        Scene::from_description_with_report(&desc, path.parent().unwrap_or(Path::new("")))
+    }
+
+    pub fn from_description(desc: &SceneDescription, base_dir: &Path) -> Result<Scene, SceneError> {
+        Scene::from_description_with_report(desc, base_dir).map(|(scene, _)| scene)
+    }
+
+    /// Like `from_description`, also returning what went into loading the
+    /// scene.
+    pub fn from_description_with_report(
+        desc: &SceneDescription,
+        base_dir: &Path,
+    ) -> Result<(Scene, LoadReport), SceneError> {
+        let mut scene = Scene::new();
+        let mut report = LoadReport::default();
+
+        if let Some(name) = desc
+            .render
+            .aovs
+            .iter()
+            .find(|n| Aov::from_name(n).is_none())
+        {
+            return Err(SceneError::UnknownAov(name.clone()));
+        }
+
+        let materials: BTreeMap<&str, Arc<dyn RTMaterial>> = desc
+            .materials
+            .iter()
+            .map(|(name, mat)| (name.as_str(), scene.add_material(name, mat.to_material())))
+            .collect();
+
+        for (i, obj) in desc.objects.iter().enumerate() {
+            let material = match obj {
+                ObjectDescription::Sphere { material, .. }
+                | ObjectDescription::Plane { material, .. }
+                | ObjectDescription::Mesh { material, .. } => materials
+                    .get(material.as_str())
+                    .cloned()
+                    .ok_or_else(|| SceneError::UnknownMaterial {
+                        object: i,
+                        material: material.clone(),
+                    })?,
+            };
+
+            let object: Box<dyn SceneObject> = match obj {
+                ObjectDescription::Sphere { center, radius, .. } => {
+                    Box::new(Sphere::new(vec3(*center), *radius, material))
+                }
+                ObjectDescription::Plane {
+                    position, normal, ..
+                } => Box::new(Plane::new(
+                    vec3(*position),
+                    vec3(*normal).normalized(),
+                    material,
+                )),
+                ObjectDescription::Mesh {
+                    path,
+                    transform,
+                    bvh,
+                    ..
+                } => {
+                    let path = base_dir.join(path);
+                    let mut mesh =
+                        Mesh::load_obj(&path.to_string_lossy(), transform.to_transform(), material)
+                            .map_err(|reason| SceneError::Mesh { object: i, reason })?;
+                    let build_time =
+                        mesh.build_with(bvh.as_ref().unwrap_or(&desc.render.bvh).to_builder());
+                    report.meshes.push(MeshReport {
+                        object: i,
+                        path,
+                        triangles: mesh.triangle_count(),
+                        build_time,
+                    });
+                    mesh.transform_description = transform.clone();
+                    mesh.bvh_description = bvh.clone();
+                    Box::new(mesh)
+                }
+            };
+
+            scene.add_object(object);
+        }
+
+        for light in &desc.lights {
+            scene.add_light(light.to_light());
+        }
+
+        scene.set_environment(desc.environment.to_environment(base_dir)?);
+        scene.camera = desc.camera.to_camera();
+        if desc.camera.focus_distance.is_none() && desc.camera.target.is_none() {
+            let mut camera = scene.camera.clone();
+            camera.autofocus(&scene);
+            scene.camera = camera;
+        }
+        scene.settings = desc.render.clone();
+
+        Ok((scene, report))
+    }
+
+    /// Saves the scene in the format read by `from_file`. Mesh paths are
+    /// written relative to the directory of the saved file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        self.to_description(path.parent().unwrap_or(Path::new("")))
+            .save(path)
+    }
+
+    pub fn to_description(&self, base_dir: &Path) -> SceneDescription {
+        let mut materials: Vec<(String, Arc<dyn RTMaterial>)> = self.materials().to_vec();
+        let mut objects = Vec::new();
+
+        for obj in self.objects() {
+            let material = obj.material();
+            let name = match materials.iter().find(|(_, m)| Arc::ptr_eq(m, &material)) {
+                Some((name, _)) => name.clone(),
+                None => {
+                    let name = (0..)
+                        .map(|i| format!("material{i}"))
+                        .find(|name| materials.iter().all(|(n, _)| n != name))
+                        .unwrap();
+                    materials.push((name.clone(), material));
+                    name
+                }
+            };
+
+            let mut desc = obj.to_description(name);
+            if let ObjectDescription::Mesh { path, .. } = &mut desc {
+                *path = relative_path(Path::new(path), base_dir)
+                    .to_string_lossy()
+                    .into_owned();
+            }
+            objects.push(desc);
+        }
+
+        let mut environment = self.environment().to_description();
+        if let EnvironmentDescription::Map { path, .. } = &mut environment {
+            *path = relative_path(Path::new(path), base_dir)
+                .to_string_lossy()
+                .into_owned();
+        }
+
+        SceneDescription {
+            camera: CameraDescription::from_camera(&self.camera),
+            render: self.settings.clone(),
+            materials: materials
+                .iter()
+                .map(|(name, mat)| (name.clone(), mat.to_description()))
+                .collect(),
+            objects,
+            lights: self
+                .lights()
+                .iter()
+                .map(|light| light.to_description())
+                .collect(),
+            environment,
+        }
+    }
+}
+
+impl SceneDescription {
+    pub fn to_toml(&self) -> Result<String, SceneError> {
+        let mut value = toml::Value::try_from(self).map_err(SceneError::Serialize)?;
+        tidy_floats(&mut value);
+        toml::to_string(&value).map_err(SceneError::Serialize)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_toml()?).map_err(|err| SceneError::Io(path.into(), err))
+    }
+}
+
+impl CameraDescription {
+    pub fn from_camera(camera: &RayCamera) -> Self {
+        Self {
+            position: arr3(camera.position),
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            target: None,
+            up: arr3(camera.up),
+            roll: camera.roll,
+            fov: camera.fov,
+            aperture: Some(camera.aperture),
+            focus_distance: Some(camera.focus_distance),
+            projection: match camera.projection {
+                Projection::Perspective => ProjectionDescription::Perspective,
+                Projection::Orthographic { width } => ProjectionDescription::Orthographic { width },
+                Projection::Fisheye => ProjectionDescription::Fisheye,
+                Projection::Equirectangular => ProjectionDescription::Equirectangular,
+            },
+            exposure: ExposureDescription::from_exposure(&camera.exposure),
+        }
+    }
+
+    /// Builds the camera. Autofocus needs the scene, so `from_description`
+    /// takes care of a missing `focus_distance` when there is no target.
+    pub fn to_camera(&self) -> RayCamera {
+        let mut camera = RayCamera::new(vec3(self.position));
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.update_direction();
+        camera.up = vec3(self.up);
+        if let Some(target) = self.target {
+            camera.set_look_at(vec3(target), vec3(self.up));
+        }
+        camera.roll = self.roll;
+        camera.fov = self.fov;
+        camera.exposure = self.exposure.to_exposure();
+        match (self.aperture, self.exposure.f_number) {
+            (Some(aperture), _) => camera.aperture = aperture,
+            (None, Some(f_number)) => camera.set_f_number(f_number),
+            (None, None) => camera.aperture = 0f32,
+        }
+        camera.focus_distance = match (self.focus_distance, self.target) {
+            (Some(distance), _) => distance,
+            (None, Some(target)) => vec3(target).distance_to(camera.position),
+            (None, None) => camera.focus_distance,
+        };
+        camera.projection = match self.projection {
+            ProjectionDescription::Perspective => Projection::Perspective,
+            ProjectionDescription::Orthographic { width } => Projection::Orthographic { width },
+            ProjectionDescription::Fisheye => Projection::Fisheye,
+            ProjectionDescription::Equirectangular => Projection::Equirectangular,
+        };
+        camera
+    }
+}
+
+impl ExposureDescription {
+    pub fn from_exposure(exposure: &Exposure) -> Self {
+        Self {
+            shutter: exposure.shutter,
+            f_number: Some(exposure.f_number),
+            iso: exposure.iso,
+            compensation: exposure.compensation,
+            white_balance: exposure.white_balance,
+        }
+    }
+
+    pub fn to_exposure(&self) -> Exposure {
+        let default = Exposure::default();
+        Exposure {
+            shutter: self.shutter,
+            f_number: self.f_number.unwrap_or(default.f_number),
+            iso: self.iso,
+            compensation: self.compensation,
+            white_balance: self.white_balance,
+        }
+    }
+
+    fn is_neutral(&self) -> bool {
+        self.to_exposure() == Exposure::default()
+    }
+}
+
+impl MaterialDescription {
+    pub fn to_material(&self) -> Arc<dyn RTMaterial> {
+        match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Arc::new(LambertianMaterial::new(vec3(*albedo)))
+            }
+            MaterialDescription::Metal { albedo, roughness } => {
+                Arc::new(MetalMaterial::from_fuzz(vec3(*albedo), *roughness))
+            }
+            MaterialDescription::Emissive { emit } => Arc::new(EmissiveMaterial::new(vec3(*emit))),
+            MaterialDescription::Dielectric { ior } => Arc::new(DielectricMaterial::new(*ior)),
+        }
+    }
+}
+
+impl LightDescription {
+    pub fn to_light(&self) -> Box<dyn Light> {
+        match self {
+            LightDescription::Point {
+                position,
+                intensity,
+            } => Box::new(PointLight::new(vec3(*position), vec3(*intensity))),
+            LightDescription::Spot {
+                position,
+                direction,
+                intensity,
+                angle,
+                falloff,
+            } => Box::new(SpotLight::new(
+                vec3(*position),
+                vec3(*direction),
+                vec3(*intensity),
+                *angle,
+                *falloff,
+            )),
+            LightDescription::Directional {
+                direction,
+                irradiance,
+            } => Box::new(DirectionalLight::new(vec3(*direction), vec3(*irradiance))),
+        }
+    }
+}
+
+impl EnvironmentDescription {
+    /// Builds the environment, loading map images relative to `base_dir`.
+    pub fn to_environment(&self, base_dir: &Path) -> Result<Box<dyn Environment>, SceneError> {
+        Ok(match self {
+            EnvironmentDescription::Constant { color } => {
+                Box::new(ConstantEnvironment::new(vec3(*color)))
+            }
+            EnvironmentDescription::Gradient => Box::new(GradientEnvironment),
+            EnvironmentDescription::Map {
+                path,
+                rotation,
+                intensity,
+            } => {
+                let path = base_dir.join(path);
+                Box::new(
+                    EnvironmentMap::load(&path.to_string_lossy(), *rotation, *intensity)
+                        .map_err(|err| SceneError::Image(path, err))?,
+                )
+            }
+            EnvironmentDescription::Sky {
+                sun_elevation,
+                sun_azimuth,
+                turbidity,
+                intensity,
+            } => Box::new(SkyEnvironment::new(
+                *sun_elevation,
+                *sun_azimuth,
+                *turbidity,
+                *intensity,
+            )),
+        })
+    }
+}
+
+pub(crate) fn vec3(v: [f32; 3]) -> Vector3 {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+pub(crate) fn arr3(v: Vector3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn default_intensity() -> f32 {
+    1f32
+}
+
+fn default_turbidity() -> f32 {
+    3f32
+}
+
+fn default_bins() -> usize {
+    SahSettings::default().bins
+}
+
+fn default_max_leaf_size() -> usize {
+    SahSettings::default().max_leaf_size
+}
+
+fn default_traversal_cost() -> f32 {
+    SahSettings::default().traversal_cost
+}
+
+fn default_intersection_cost() -> f32 {
+    SahSettings::default().intersection_cost
+}
+
+fn default_linear_max_leaf_size() -> usize {
+    LinearSettings::default().max_leaf_size
+}
+
+fn default_sah_levels() -> usize {
+    LinearSettings::default().sah_levels
+}
+
+fn is_zero(v: &[f32; 3]) -> bool {
+    *v == [0f32; 3]
+}
+
+fn is_zero_f32(v: &f32) -> bool {
+    *v == 0f32
+}
+
+fn is_world_up(v: &[f32; 3]) -> bool {
+    *v == [0f32, 1f32, 0f32]
+}
+
+fn is_one(v: &[f32; 3]) -> bool {
+    *v == [1f32; 3]
+}
+
+/// Every float in a description started out as an `f32`, so printing the
+/// shortest `f32` representation keeps `0.73` from turning into
+/// `0.7300000190734863` while still reading back to the same value.
+fn tidy_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => *f = (*f as f32).to_string().parse().unwrap_or(*f),
+        toml::Value::Array(values) => values.iter_mut().for_each(tidy_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| tidy_floats(v)),
+        _ => {}
+    }
+}
+
+fn relative_path(path: &Path, base_dir: &Path) -> PathBuf {
+    let base_dir = if base_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base_dir
+    };
+
+    let (Ok(path), Ok(base_dir)) = (fs::canonicalize(path), fs::canonicalize(base_dir)) else {
+        return path.to_path_buf();
+    };
+
+    let common = path
+        .components()
+        .zip(base_dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut rel = PathBuf::new();
+    base_dir
+        .components()
+        .skip(common)
+        .for_each(|_| rel.push(".."));
+    path.components().skip(common).for_each(|c| rel.push(c));
+    rel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(desc: &SceneDescription) -> toml::Value {
+        toml::Value::try_from(desc).unwrap()
+    }
+
+    #[test]
+    fn example_scenes_survive_a_round_trip() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let parsed: SceneDescription =
+                toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            let saved = Scene::from_file(&path).unwrap().to_description(&dir);
+            let reloaded: SceneDescription = toml::from_str(&saved.to_toml().unwrap()).unwrap();
+            let resaved = Scene::from_description(&reloaded, &dir)
+                .unwrap()
+                .to_description(&dir);
+
+            assert_eq!(value(&saved), value(&resaved), "{}", path.display());
+            // nothing is filled in for materials, so they come back as written
+            assert_eq!(
+                value(&parsed)["materials"],
+                value(&saved)["materials"],
+                "{}",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::rendering::Framebuffer;
+use crate::scene::description::{arr3, EnvironmentDescription};
+use crate::utils::{luminance, rand_f32};
+use image::ImageResult;
+use raylib::math::Vector3;
+use std::f32::consts::PI;
+
+/// Light arriving from infinitely far away, seen by rays that miss the scene.
+pub trait Environment: Send + Sync {
+    /// Light arriving along the unit direction `dir`, which points away from the scene.
+    fn radiance(&self, dir: Vector3) -> Vector3;
+    /// Picks a direction to send a shadow ray along, with its solid angle
+    /// density. Environments without importance sampling return `None` and
+    /// are only found by BSDF sampling.
+    fn sample(&self) -> Option<(Vector3, f32)> {
+        None
+    }
+    /// Density with which `sample` picks `dir`.
+    fn pdf(&self, _dir: Vector3) -> f32 {
+        0f32
+    }
+    fn to_description(&self) -> EnvironmentDescription;
+}
+
+/// The same color in every direction.
+pub struct ConstantEnvironment {
+    pub color: Vector3,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Vector3) -> ConstantEnvironment {
+        ConstantEnvironment { color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _dir: Vector3) -> Vector3 {
+        self.color
+    }
+
+    fn to_description(&self) -> EnvironmentDescription {
+        EnvironmentDescription::Constant {
+            color: arr3(self.color),
+        }
+    }
+}
+
+/// White at the horizon fading to light blue overhead.
+pub struct GradientEnvironment;
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, dir: Vector3) -> Vector3 {
+        let t = 0.5f32 * (dir.y + 1.0f32);
+        return Vector3::new(
+            (1f32 - t) + (t * 138f32 / 255f32),
+            (1f32 - t) + (t * 188f32 / 255f32),
+            1f32,
+        );
+    }
+
+    fn to_description(&self) -> EnvironmentDescription {
+        EnvironmentDescription::Gradient
+    }
+}
+
+/// An equirectangular (latitude-longitude) image, importance sampled by
+/// luminance. The center of the image faces +z and `rotation` turns it
+/// around the y axis, in degrees.
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    distribution: Distribution2D,
+    pub rotation: f32,
+    pub intensity: f32,
+    /// The image file the map was loaded from, if any.
+    pub source: Option<String>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Framebuffer, rotation: f32, intensity: f32) -> EnvironmentMap {
+        let (width, height) = (image.width, image.height);
+        // weighted by sin(theta) since rows near the poles cover less of the sphere
+        let weights = image
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let theta = PI * ((i / width) as f32 + 0.5f32) / height as f32;
+                luminance(color).max(0f32) * theta.sin()
+            })
+            .collect();
+
+        EnvironmentMap {
+            distribution: Distribution2D::new(weights, width, height),
+            image,
+            rotation,
+            intensity,
+            source: None,
+        }
+    }
+
+    /// Loads any image `Framebuffer::load` understands, `.hdr` and `.exr`
+    /// being the useful ones.
+    pub fn load(path: &str, rotation: f32, intensity: f32) -> ImageResult<EnvironmentMap> {
+        let mut map = EnvironmentMap::new(Framebuffer::load(path)?, rotation, intensity);
+        map.source = Some(String::from(path));
+        Ok(map)
+    }
+
+    fn to_uv(&self, dir: Vector3) -> (f32, f32) {
+        let phi = dir.x.atan2(dir.z) + self.rotation.to_radians();
+        let u = (0.5f32 + phi / (2f32 * PI)).rem_euclid(1f32);
+        let v = dir.y.clamp(-1f32, 1f32).acos() / PI;
+        (u, v)
+    }
+
+    fn to_dir(&self, u: f32, v: f32) -> Vector3 {
+        let phi = 2f32 * PI * (u - 0.5f32) - self.rotation.to_radians();
+        let theta = PI * v;
+        Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        )
+    }
+
+    fn pixel(&self, u: f32, v: f32) -> (usize, usize) {
+        (
+            ((u * self.image.width as f32) as usize).min(self.image.width - 1),
+            ((v * self.image.height as f32) as usize).min(self.image.height - 1),
+        )
+    }
+
+    /// Converts the probability of picking a pixel into a solid angle density.
+    fn pixel_pdf(&self, x: usize, y: usize, v: f32) -> f32 {
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0f32 {
+            return 0f32;
+        }
+        let pixels = (self.image.width * self.image.height) as f32;
+        self.distribution.pmf(x, y) * pixels / (2f32 * PI * PI * sin_theta)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, dir: Vector3) -> Vector3 {
+        let (u, v) = self.to_uv(dir);
+        let (x, y) = self.pixel(u, v);
+        self.image.get_pixel(x, y) * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vector3, f32)> {
+        let (x, y) = self.distribution.sample(rand_f32(), rand_f32())?;
+        let u = (x as f32 + rand_f32()) / self.image.width as f32;
+        let v = (y as f32 + rand_f32()) / self.image.height as f32;
+
+        let pdf = self.pixel_pdf(x, y, v);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some((self.to_dir(u, v), pdf))
+    }
+
+    fn pdf(&self, dir: Vector3) -> f32 {
+        let (u, v) = self.to_uv(dir);
+        let (x, y) = self.pixel(u, v);
+        self.pixel_pdf(x, y, v)
+    }
+
+    fn to_description(&self) -> EnvironmentDescription {
+        EnvironmentDescription::Map {
+            path: self.source.clone().unwrap_or_default(),
+            rotation: self.rotation,
+            intensity: self.intensity,
+        }
+    }
+}
+
+/// Picks pixels of an image with probability proportional to their weight,
+/// first the row from the marginal distribution, then the column within it.
+struct Distribution2D {
+    width: usize,
+    height: usize,
+    weights: Vec<f32>,
+    total: f32,
+    /// Normalized running sums within each row.
+    conditional: Vec<f32>,
+    /// Normalized running sum of the row totals.
+    marginal: Vec<f32>,
+}
+
+impl Distribution2D {
+    fn new(weights: Vec<f32>, width: usize, height: usize) -> Distribution2D {
+        let mut conditional = vec![0f32; width * height];
+        let mut row_sums = vec![0f32; height];
+
+        for y in 0..height {
+            let row = &weights[y * width..(y + 1) * width];
+            let cdf = &mut conditional[y * width..(y + 1) * width];
+
+            let mut sum = 0f32;
+            for (c, w) in cdf.iter_mut().zip(row) {
+                sum += w;
+                *c = sum;
+            }
+            row_sums[y] = sum;
+
+            cdf.iter_mut().enumerate().for_each(|(x, c)| {
+                *c = if sum > 0f32 {
+                    *c / sum
+                } else {
+                    (x + 1) as f32 / width as f32
+                }
+            });
+        }
+
+        let total: f32 = row_sums.iter().sum();
+        let mut sum = 0f32;
+        let marginal = row_sums
+            .iter()
+            .map(|s| {
+                sum += s;
+                sum / total
+            })
+            .collect();
+
+        Distribution2D {
+            width,
+            height,
+            weights,
+            total,
+            conditional,
+            marginal,
+        }
+    }
+
+    fn sample(&self, u1: f32, u2: f32) -> Option<(usize, usize)> {
+        if self.total <= 0f32 {
+            return None;
+        }
+
+        let y = self
+            .marginal
+            .partition_point(|&c| c <= u1)
+            .min(self.height - 1);
+        let row = &self.conditional[y * self.width..(y + 1) * self.width];
+        let x = row.partition_point(|&c| c <= u2).min(self.width - 1);
+
+        Some((x, y))
+    }
+
+    fn pmf(&self, x: usize, y: usize) -> f32 {
+        if self.total <= 0f32 {
+            return 0f32;
+        }
+        self.weights[y * self.width + x] / self.total
+    }
+}
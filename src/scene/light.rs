@@ -0,0 +1,172 @@
+use crate::scene::description::{arr3, LightDescription};
+use raylib::math::Vector3;
+
+/// Light arriving at a point from a `Light`.
+pub struct LightSample {
+    /// Unit direction from the lit point towards the light.
+    pub wi: Vector3,
+    /// Distance to the light, infinite for directional lights. Shadow rays
+    /// only need to check for occluders closer than this.
+    pub dist: f32,
+    /// Incoming light along `wi`, including the falloff with distance.
+    pub radiance: Vector3,
+}
+
+/// An analytic light with no surface. Such lights can't be hit by rays, so
+/// the renderer only reaches them through `sample`.
+pub trait Light: Send + Sync {
+    /// Light arriving at `point`, not accounting for occlusion. `None` when
+    /// `point` isn't lit at all.
+    fn sample(&self, point: Vector3) -> Option<LightSample>;
+    fn to_description(&self) -> LightDescription;
+}
+
+/// Emits `intensity` uniformly in all directions from `position`.
+pub struct PointLight {
+    pub position: Vector3,
+    pub intensity: Vector3,
+}
+
+impl PointLight {
+    pub fn new(position: Vector3, intensity: Vector3) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Vector3) -> Option<LightSample> {
+        let (wi, dist) = towards(point, self.position)?;
+
+        Some(LightSample {
+            wi,
+            dist,
+            radiance: self.intensity / (dist * dist),
+        })
+    }
+
+    fn to_description(&self) -> LightDescription {
+        LightDescription::Point {
+            position: arr3(self.position),
+            intensity: arr3(self.intensity),
+        }
+    }
+}
+
+/// A point light restricted to a cone around `direction`, which needn't be
+/// normalized. `angle` is the half angle of the cone and `falloff` the width
+/// of its soft edge, both in degrees.
+pub struct SpotLight {
+    pub position: Vector3,
+    pub direction: Vector3,
+    pub intensity: Vector3,
+    pub angle: f32,
+    pub falloff: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vector3,
+        direction: Vector3,
+        intensity: Vector3,
+        angle: f32,
+        falloff: f32,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction,
+            intensity,
+            angle,
+            falloff,
+        }
+    }
+
+    /// Fraction of `intensity` emitted along `dir`, smoothly going from one
+    /// inside the cone to zero at its edge.
+    fn cone_falloff(&self, dir: Vector3) -> f32 {
+        let cos_theta = dir.dot(self.direction.normalized());
+        let cos_outer = self.angle.to_radians().cos();
+        let cos_inner = (self.angle - self.falloff).max(0f32).to_radians().cos();
+
+        if cos_theta < cos_outer {
+            return 0f32;
+        }
+        if cos_theta >= cos_inner {
+            return 1f32;
+        }
+
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3f32 - 2f32 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Vector3) -> Option<LightSample> {
+        let (wi, dist) = towards(point, self.position)?;
+        let falloff = self.cone_falloff(-wi);
+        if falloff <= 0f32 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            dist,
+            radiance: self.intensity * (falloff / (dist * dist)),
+        })
+    }
+
+    fn to_description(&self) -> LightDescription {
+        LightDescription::Spot {
+            position: arr3(self.position),
+            direction: arr3(self.direction),
+            intensity: arr3(self.intensity),
+            angle: self.angle,
+            falloff: self.falloff,
+        }
+    }
+}
+
+/// Parallel light travelling along `direction` (not necessarily unit length),
+/// like the sun. `irradiance` is what a surface facing the light receives.
+pub struct DirectionalLight {
+    pub direction: Vector3,
+    pub irradiance: Vector3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3, irradiance: Vector3) -> DirectionalLight {
+        DirectionalLight {
+            direction,
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Vector3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction.normalized(),
+            dist: f32::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+
+    fn to_description(&self) -> LightDescription {
+        LightDescription::Directional {
+            direction: arr3(self.direction),
+            irradiance: arr3(self.irradiance),
+        }
+    }
+}
+
+/// Unit direction and distance from `point` to `target`.
+fn towards(point: Vector3, target: Vector3) -> Option<(Vector3, f32)> {
+    let to_light = target - point;
+    let dist = to_light.length();
+    if dist <= 0f32 {
+        return None;
+    }
+    Some((to_light / dist, dist))
+}
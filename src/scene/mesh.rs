@@ -1,4 +1,4 @@
-use std::{fs, sync::Arc};
+use std::{fs, sync::Arc, time::Duration};
 
 use raylib::math::{Vector2, Vector3};
 use wavefront_obj::obj;
@@ -6,137 +6,194 @@ use wavefront_obj::obj;
 use crate::{
     math::Transform,
     rendering::{RTMaterial, Ray},
+    utils::rand_f32,
 };
 
 use super::{
-    bvh::{self, BVH},
-    HitData, SceneObject, Triangle,
+    bvh::{BVHBuilder, BVH},
+    description::{BVHDescription, ObjectDescription, TransformDescription},
+    HitData, SceneObject, Triangle, AABB,
 };
 
 pub struct Mesh {
     bvh: BVH,
     transform: Transform,
     material: Arc<dyn RTMaterial>,
+    /// Running sum of the world space triangle areas, in BVH order, for
+    /// picking triangles proportional to their area.
+    area_cdf: Vec<f32>,
+    /// World space box around the transformed triangles, `None` while there
+    /// are none.
+    bounds: Option<AABB>,
+    /// The obj file the mesh was loaded from, if any.
+    pub source: Option<String>,
+    /// How `transform` was written in the scene file, kept so saving
+    /// doesn't turn it into a raw matrix.
+    pub transform_description: TransformDescription,
+    /// The builder the scene file picked for this mesh alone, if any, kept
+    /// so saving writes it back.
+    pub bvh_description: Option<BVHDescription>,
 }
 
 impl Mesh {
     pub fn new(tris: Vec<Triangle>, transform: Transform, material: Arc<dyn RTMaterial>) -> Self {
-        Self {
+        let mut mesh = Self {
             transform,
             material,
             bvh: BVH::new(tris),
+            area_cdf: Vec::new(),
+            bounds: None,
+            source: None,
+            transform_description: TransformDescription::from_matrix(transform.m),
+            bvh_description: None,
+        };
+        mesh.update_world_space();
+        mesh
+    }
+
+    /// Builds the BVH and returns how long that took. This reorders the
+    /// triangles, so the area table is rebuilt too.
+    pub fn build(&mut self) -> Duration {
+        self.build_with(BVHBuilder::default())
+    }
+
+    /// Like `build`, with the given builder instead of the default one.
+    pub fn build_with(&mut self, builder: BVHBuilder) -> Duration {
+        let build_time = self.bvh.build_with(builder);
+        self.update_world_space();
+        build_time
+    }
+
+    /// Number of triangles in the mesh.
+    pub fn triangle_count(&self) -> usize {
+        self.bvh.triangles().len()
+    }
+
+    /// Recomputes the area table and the world bounds from the triangles.
+    fn update_world_space(&mut self) {
+        let mut total = 0f32;
+        self.area_cdf = self
+            .bvh
+            .triangles()
+            .iter()
+            .map(|tri| {
+                total += self.world_triangle(tri).area();
+                total
+            })
+            .collect();
+        self.bounds = AABB::from_points(
+            self.bvh
+                .triangles()
+                .iter()
+                .flat_map(|tri| self.world_triangle(tri).verts),
+        );
+    }
+
+    fn world_triangle(&self, tri: &Triangle) -> Triangle {
+        Triangle {
+            verts: tri.verts.map(|v| v.transform_with(self.transform.m)),
+            normals: None,
+            uvs: None,
         }
     }
 
     pub fn from_obj(path: &str, transform: Transform, material: Arc<dyn RTMaterial>) -> Self {
-        if let Ok(file) = fs::read_to_string(path) {
-            match obj::parse(file) {
-                Ok(res) => {
-                    if let Some(obj) = res.objects.get(0) {
-                        println!("Loading {} from obj file at {path}", obj.name);
-                        let tris: Vec<Triangle> = obj
-                            .geometry
-                            .iter()
-                            .flat_map(|g| {
-                                g.shapes.iter().filter_map(|s| match s.primitive {
-                                    obj::Primitive::Triangle(
-                                        (v0, t0, n0),
-                                        (v1, t1, n1),
-                                        (v2, t2, n2),
-                                    ) => {
-                                        let v0 = obj.vertices[v0];
-                                        let v1 = obj.vertices[v1];
-                                        let v2 = obj.vertices[v2];
-
-                                        let mut tri = Triangle {
-                                            uvs: None,
-                                            normals: None,
-                                            verts: [
-                                                Vector3::new(v0.x as f32, v0.y as f32, v0.z as f32),
-                                                Vector3::new(v1.x as f32, v1.y as f32, v1.z as f32),
-                                                Vector3::new(v2.x as f32, v2.y as f32, v2.z as f32),
-                                            ],
-                                        };
-
-                                        match (t0, t1, t2) {
-                                            (Some(t0), Some(t1), Some(t2)) => {
-                                                let t0 = obj.tex_vertices[t0];
-                                                let t1 = obj.tex_vertices[t1];
-                                                let t2 = obj.tex_vertices[t2];
-                                                tri.uvs = Some([
-                                                    Vector3::new(
-                                                        t0.u as f32,
-                                                        t0.v as f32,
-                                                        t0.w as f32,
-                                                    ),
-                                                    Vector3::new(
-                                                        t1.u as f32,
-                                                        t1.v as f32,
-                                                        t1.w as f32,
-                                                    ),
-                                                    Vector3::new(
-                                                        t2.u as f32,
-                                                        t2.v as f32,
-                                                        t2.w as f32,
-                                                    ),
-                                                ])
-                                            }
-                                            _ => {}
-                                        }
-
-                                        match (n0, n1, n2) {
-                                            (Some(n0), Some(n1), Some(n2)) => {
-                                                let n0 = obj.normals[n0];
-                                                let n1 = obj.normals[n1];
-                                                let n2 = obj.normals[n2];
-                                                tri.normals = Some([
-                                                    Vector3::new(
-                                                        n0.x as f32,
-                                                        n0.y as f32,
-                                                        n0.z as f32,
-                                                    ),
-                                                    Vector3::new(
-                                                        n1.x as f32,
-                                                        n1.y as f32,
-                                                        n1.z as f32,
-                                                    ),
-                                                    Vector3::new(
-                                                        n2.x as f32,
-                                                        n2.y as f32,
-                                                        n2.z as f32,
-                                                    ),
-                                                ])
-                                            }
-                                            _ => {}
-                                        }
-
-                                        Some(tri)
-                                    }
-                                    _ => None,
-                                })
-                            })
-                            .collect();
-
-                        let mut mesh = Mesh {
-                            bvh: BVH::new(tris),
-                            transform,
-                            material,
+        match Mesh::load_obj(path, transform, Arc::clone(&material)) {
+            Ok(mut mesh) => {
+                mesh.build();
+                mesh
+            }
+            Err(err) => {
+                println!("{err}");
+                let mut mesh = Mesh::new(Vec::new(), transform, material);
+                mesh.source = Some(String::from(path));
+                mesh
+            }
+        }
+    }
+
+    /// Like `from_obj`, but reports why the file could not be loaded instead of
+    /// falling back to an empty mesh. The BVH is left for the caller to build
+    /// with `build_with`, so it can pick the builder and report the time.
+    pub fn load_obj(
+        path: &str,
+        transform: Transform,
+        material: Arc<dyn RTMaterial>,
+    ) -> Result<Self, String> {
+        let tris = Mesh::load_triangles(path)?;
+
+        let mut mesh = Mesh::new(tris, transform, material);
+        mesh.source = Some(String::from(path));
+
+        Ok(mesh)
+    }
+
+    /// Reads the triangles of the first object in an obj file, in object space.
+    pub fn load_triangles(path: &str) -> Result<Vec<Triangle>, String> {
+        let file =
+            fs::read_to_string(path).map_err(|err| format!("Failed to read {path}: {err}"))?;
+        let res = obj::parse(file).map_err(|err| format!("Failed to parse {path}: {err}"))?;
+        let obj = res
+            .objects
+            .get(0)
+            .ok_or_else(|| format!("No objects in {path}"))?;
+
+        let tris: Vec<Triangle> = obj
+            .geometry
+            .iter()
+            .flat_map(|g| {
+                g.shapes.iter().filter_map(|s| match s.primitive {
+                    obj::Primitive::Triangle((v0, t0, n0), (v1, t1, n1), (v2, t2, n2)) => {
+                        let v0 = obj.vertices[v0];
+                        let v1 = obj.vertices[v1];
+                        let v2 = obj.vertices[v2];
+
+                        let mut tri = Triangle {
+                            uvs: None,
+                            normals: None,
+                            verts: [
+                                Vector3::new(v0.x as f32, v0.y as f32, v0.z as f32),
+                                Vector3::new(v1.x as f32, v1.y as f32, v1.z as f32),
+                                Vector3::new(v2.x as f32, v2.y as f32, v2.z as f32),
+                            ],
                         };
-                        mesh.bvh.build();
 
-                        return mesh;
+                        match (t0, t1, t2) {
+                            (Some(t0), Some(t1), Some(t2)) => {
+                                let t0 = obj.tex_vertices[t0];
+                                let t1 = obj.tex_vertices[t1];
+                                let t2 = obj.tex_vertices[t2];
+                                tri.uvs = Some([
+                                    Vector3::new(t0.u as f32, t0.v as f32, t0.w as f32),
+                                    Vector3::new(t1.u as f32, t1.v as f32, t1.w as f32),
+                                    Vector3::new(t2.u as f32, t2.v as f32, t2.w as f32),
+                                ])
+                            }
+                            _ => {}
+                        }
+
+                        match (n0, n1, n2) {
+                            (Some(n0), Some(n1), Some(n2)) => {
+                                let n0 = obj.normals[n0];
+                                let n1 = obj.normals[n1];
+                                let n2 = obj.normals[n2];
+                                tri.normals = Some([
+                                    Vector3::new(n0.x as f32, n0.y as f32, n0.z as f32),
+                                    Vector3::new(n1.x as f32, n1.y as f32, n1.z as f32),
+                                    Vector3::new(n2.x as f32, n2.y as f32, n2.z as f32),
+                                ])
+                            }
+                            _ => {}
+                        }
+
+                        Some(tri)
                     }
-                }
-                Err(err) => {
-                    println!("Failed to parse: {err}")
-                }
-            }
-        }
-        return Mesh {
-            bvh: BVH::new(Vec::new()),
-            transform,
-            material,
-        };
+                    _ => None,
+                })
+            })
+            .collect();
+
+        Ok(tris)
     }
 }
 
@@ -145,11 +202,19 @@ impl SceneObject for Mesh {
         let t_ray = ray.transform(&self.transform);
 
         if let Some(hit) = self.bvh.intersect(&t_ray) {
-            return Some(HitData::new(
+            let normal_matrix = self.transform.inv.transposed();
+            let mut world_hit = HitData::new(
                 hit.position.transform_with(self.transform.m),
-                hit.normal.transform_with(self.transform.inv.transposed()),
+                hit.normal.transform_with(normal_matrix).normalized(),
                 hit.bary,
-            ));
+            );
+            world_hit.geometric_normal = hit
+                .geometric_normal
+                .transform_with(normal_matrix)
+                .normalized();
+            world_hit.uv = hit.uv;
+            world_hit.node_hits = hit.node_hits;
+            return Some(world_hit);
         }
         None
     }
@@ -158,7 +223,46 @@ impl SceneObject for Mesh {
         return Arc::clone(&self.material);
     }
 
+    fn area(&self) -> Option<f32> {
+        Some(self.area_cdf.last().copied().unwrap_or(0f32))
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        self.bounds.clone()
+    }
+
+    fn sample_surface(&self) -> Option<HitData> {
+        let total = *self.area_cdf.last()?;
+        let target = rand_f32() * total;
+        let idx = self
+            .area_cdf
+            .partition_point(|&sum| sum <= target)
+            .min(self.area_cdf.len() - 1);
+
+        let tri = self.world_triangle(&self.bvh.triangles()[idx]);
+        let normal = (tri.verts[1] - tri.verts[0])
+            .cross(tri.verts[2] - tri.verts[0])
+            .normalized();
+
+        Some(HitData::new(
+            tri.sample_point(rand_f32(), rand_f32()),
+            normal,
+            Vector3::zero(),
+        ))
+    }
+
     fn update(&self, _: f32) {
         todo!()
     }
+
+    /// Meshes built from triangles in code have no file to point at; they are
+    /// saved with an empty path and fail to load until one is filled in.
+    fn to_description(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Mesh {
+            path: self.source.clone().unwrap_or_default(),
+            material,
+            transform: self.transform_description.clone(),
+            bvh: self.bvh_description.clone(),
+        }
+    }
 }
@@ -1,13 +1,23 @@
 pub mod aabb;
 pub mod bvh;
+pub mod description;
+pub mod environment;
+pub mod light;
 pub mod mesh;
 pub mod models;
+pub mod object_bvh;
 pub mod plane;
+pub mod presets;
+pub mod sky;
 pub mod sphere;
 pub mod triangle;
 
 pub use aabb::AABB;
+pub use description::{RenderSettings, SceneError};
+pub use environment::{ConstantEnvironment, Environment, EnvironmentMap, GradientEnvironment};
+pub use light::{DirectionalLight, Light, LightSample, PointLight, SpotLight};
 pub use models::{HitData, SceneObject};
 pub use plane::Plane;
+pub use sky::SkyEnvironment;
 pub use sphere::Sphere;
 pub use triangle::Triangle;
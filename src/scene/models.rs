@@ -1,20 +1,168 @@
-use crate::rendering::{RTMaterial, Ray};
-use raylib::math::Vector3;
-use std::sync::Arc;
+use crate::rendering::{RTMaterial, Ray, RayCamera};
+use crate::scene::description::ObjectDescription;
+use crate::scene::object_bvh::ObjectBVH;
+use crate::scene::{Environment, GradientEnvironment, Light, RenderSettings, AABB};
+use crate::utils::rand_f32;
+use raylib::math::{Vector2, Vector3};
+use std::sync::{Arc, OnceLock};
 
 pub struct Scene {
     scene_objects: Vec<Box<dyn SceneObject>>,
+    /// Tree over the objects with bounds, built on the first ray after
+    /// objects were added.
+    object_bvh: OnceLock<ObjectBVH>,
+    /// Indices of the objects without bounds, tested against every ray.
+    unbounded: Vec<usize>,
+    /// Indices of the objects with an emissive material and a finite area,
+    /// which the renderer samples directly.
+    emitters: Vec<usize>,
+    lights: Vec<Box<dyn Light>>,
+    environment: Box<dyn Environment>,
+    materials: Vec<(String, Arc<dyn RTMaterial>)>,
+    /// Distinct materials in the order objects started using them; a
+    /// material's position is its id.
+    material_ids: Vec<Arc<dyn RTMaterial>>,
+    /// Material id of every object.
+    object_materials: Vec<usize>,
+    pub camera: RayCamera,
+    pub settings: RenderSettings,
 }
 
 impl Scene {
     pub fn new() -> Scene {
         return Scene {
             scene_objects: Vec::new(),
+            object_bvh: OnceLock::new(),
+            unbounded: Vec::new(),
+            emitters: Vec::new(),
+            lights: Vec::new(),
+            environment: Box::new(GradientEnvironment),
+            materials: Vec::new(),
+            material_ids: Vec::new(),
+            object_materials: Vec::new(),
+            camera: RayCamera::new(Vector3::new(0f32, 2f32, 0f32)),
+            settings: RenderSettings::default(),
         };
     }
 
     pub fn add_object(&mut self, obj: Box<dyn SceneObject>) {
+        if emitter_area(obj.as_ref()).is_some() {
+            self.emitters.push(self.scene_objects.len());
+        }
+
+        let material = obj.material();
+        let material_id = match self
+            .material_ids
+            .iter()
+            .position(|m| Arc::ptr_eq(m, &material))
+        {
+            Some(id) => id,
+            None => {
+                self.material_ids.push(material);
+                self.material_ids.len() - 1
+            }
+        };
+        self.object_materials.push(material_id);
+        if obj.bounds().is_none() {
+            self.unbounded.push(self.scene_objects.len());
+        }
         self.scene_objects.push(obj);
+        self.object_bvh = OnceLock::new();
+    }
+
+    pub fn add_light(&mut self, light: Box<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    /// Registers a named material so it keeps its name when the scene is saved.
+    pub fn add_material(
+        &mut self,
+        name: &str,
+        material: Arc<dyn RTMaterial>,
+    ) -> Arc<dyn RTMaterial> {
+        self.materials
+            .push((String::from(name), Arc::clone(&material)));
+        material
+    }
+
+    pub fn materials(&self) -> &[(String, Arc<dyn RTMaterial>)] {
+        &self.materials
+    }
+
+    pub fn objects(&self) -> &[Box<dyn SceneObject>] {
+        &self.scene_objects
+    }
+
+    pub fn lights(&self) -> &[Box<dyn Light>] {
+        &self.lights
+    }
+
+    pub fn set_environment(&mut self, environment: Box<dyn Environment>) {
+        self.environment = environment;
+    }
+
+    pub fn environment(&self) -> &dyn Environment {
+        self.environment.as_ref()
+    }
+
+    pub fn emitters(&self) -> impl Iterator<Item = &Box<dyn SceneObject>> {
+        self.emitters.iter().map(|&i| &self.scene_objects[i])
+    }
+
+    /// Picks an emissive object uniformly and a point uniformly on its surface.
+    /// Returns the object, the point with its outward normal, and the solid
+    /// angle density of the direction from `origin` to the point.
+    pub fn sample_emitter(&self, origin: Vector3) -> Option<(&dyn SceneObject, HitData, f32)> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+
+        let idx = ((rand_f32() * self.emitters.len() as f32) as usize).min(self.emitters.len() - 1);
+        let obj = self.scene_objects[self.emitters[idx]].as_ref();
+        let point = obj.sample_surface()?;
+        let pdf = self.emitter_pdf(obj, origin, &point);
+
+        Some((obj, point, pdf))
+    }
+
+    /// Solid angle density with which `sample_emitter` picks the direction from
+    /// `origin` to `hit`, zero if `obj` isn't sampled as an emitter.
+    pub fn emitter_pdf(&self, obj: &dyn SceneObject, origin: Vector3, hit: &HitData) -> f32 {
+        let Some(area) = emitter_area(obj) else {
+            return 0f32;
+        };
+
+        let to_point = hit.position - origin;
+        let dist2 = to_point.dot(to_point);
+        let cos = hit.normal.dot(to_point.normalized()).abs();
+        if cos <= 0f32 {
+            return 0f32;
+        }
+
+        dist2 / (cos * area * self.emitters.len() as f32)
+    }
+
+    /// Whether anything blocks `ray` before it has travelled `max_dist`.
+    pub fn occluded(&self, ray: &Ray, max_dist: f32) -> bool {
+        let blocks = |id: usize| {
+            self.scene_objects[id]
+                .intersect(ray)
+                .is_some_and(|hit| hit.position.distance_to(ray.origin) < max_dist)
+        };
+
+        self.unbounded.iter().any(|&id| blocks(id)) || self.object_bvh().any(ray, max_dist, blocks)
+    }
+
+    fn object_bvh(&self) -> &ObjectBVH {
+        self.object_bvh.get_or_init(|| {
+            let bounds: Vec<(usize, AABB)> = self
+                .scene_objects
+                .iter()
+                .enumerate()
+                .filter_map(|(id, obj)| Some((id, obj.bounds()?)))
+                .collect();
+            ObjectBVH::build(bounds)
+        })
     }
 
     pub fn update(&self, dt: f32) {
@@ -23,31 +171,84 @@ impl Scene {
         }
     }
 
+    /// Number of distinct materials used by objects, material ids run from
+    /// zero to one less than this.
+    pub fn material_count(&self) -> usize {
+        self.material_ids.len()
+    }
+
+    /// Name the material with id `id` was registered under, if it was.
+    pub fn material_name(&self, id: usize) -> Option<&str> {
+        let material = self.material_ids.get(id)?;
+        self.materials
+            .iter()
+            .find(|(_, m)| Arc::ptr_eq(m, material))
+            .map(|(name, _)| name.as_str())
+    }
+
     pub fn intersect(&self, ray: &Ray) -> Option<(&Box<dyn SceneObject>, HitData)> {
         let mut hit_data: Option<(&Box<dyn SceneObject>, HitData)> = None;
         let mut min_hit_dist: f32 = 10e9f32;
-        for obj in &self.scene_objects {
-            match obj.intersect(ray) {
-                Some(data) => {
-                    let dist = data.position.distance_to(ray.origin);
-                    if dist < min_hit_dist {
-                        hit_data = Some((obj, data));
-                        min_hit_dist = dist;
-                    }
-                }
-                None => continue,
+        let mut test = |id: usize| {
+            let obj = &self.scene_objects[id];
+            let mut data = obj.intersect(ray)?;
+            let dist = data.position.distance_to(ray.origin);
+            if dist < min_hit_dist {
+                data.object_id = id;
+                data.material_id = self.object_materials[id];
+                hit_data = Some((obj, data));
+                min_hit_dist = dist;
             }
+            Some(dist)
+        };
+
+        // planes first, their hits let the tree skip everything behind them
+        let mut max_dist = f32::INFINITY;
+        for &id in &self.unbounded {
+            if let Some(dist) = test(id) {
+                max_dist = max_dist.min(dist);
+            }
+        }
+        self.object_bvh().intersect(ray, max_dist, test);
+
+        if let Some((_, data)) = &mut hit_data {
+            data.set_face_normal(ray);
         }
 
         return hit_data;
     }
 }
 
+/// Surface area of `obj` if it is sampled as an emitter.
+fn emitter_area(obj: &dyn SceneObject) -> Option<f32> {
+    if !obj.material().is_emissive() {
+        return None;
+    }
+    obj.area().filter(|&area| area > 0f32)
+}
+
 pub struct HitData {
     pub position: Vector3,
+    /// Outward surface normal as returned by the object; `Scene::intersect`
+    /// flips it onto the same side as `geometric_normal`.
     pub normal: Vector3,
+    /// Normal of the actual surface, which the interpolated `normal` of a
+    /// smooth mesh can differ from. `Scene::intersect` flips it to face
+    /// against the incoming ray.
+    pub geometric_normal: Vector3,
     pub bary: Vector3,
+    /// Surface parameterization, zero for objects without one.
+    pub uv: Vector2,
+    /// Nodes of the hit mesh's BVH the traversal visited, counting every box
+    /// the ray entered rather than the depth of the leaf hit. Zero for other
+    /// objects.
     pub node_hits: u32,
+    /// Whether the ray hit the outside of the surface.
+    pub front_face: bool,
+    /// Index of the object in the scene, filled in by `Scene::intersect`.
+    pub object_id: usize,
+    /// Id of the object's material, filled in by `Scene::intersect`.
+    pub material_id: usize,
 }
 
 impl HitData {
@@ -55,8 +256,26 @@ impl HitData {
         HitData {
             position,
             normal,
+            geometric_normal: normal,
             bary,
+            uv: Vector2::zero(),
             node_hits: 0,
+            front_face: true,
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+
+    /// Decides which side was hit from the geometric normal, since near the
+    /// silhouette of a smooth mesh the shading normal can point the other
+    /// way, and turns both normals towards that side.
+    pub fn set_face_normal(&mut self, ray: &Ray) {
+        self.front_face = ray.direction.dot(self.geometric_normal) < 0f32;
+        if !self.front_face {
+            self.geometric_normal = -self.geometric_normal;
+        }
+        if self.normal.dot(self.geometric_normal) < 0f32 {
+            self.normal = -self.normal;
         }
     }
 }
@@ -65,4 +284,20 @@ pub trait SceneObject: Send + Sync {
     fn intersect(&self, ray: &Ray) -> Option<HitData>;
     fn material(&self) -> Arc<dyn RTMaterial>;
     fn update(&self, dt: f32);
+    /// World space surface area, `None` for unbounded objects such as planes.
+    fn area(&self) -> Option<f32> {
+        None
+    }
+    /// World space bounding box, `None` for unbounded objects such as planes,
+    /// which every ray is tested against.
+    fn bounds(&self) -> Option<AABB> {
+        None
+    }
+    /// A point picked uniformly by area on the surface, with the outward
+    /// normal. Objects without an `area` can't be sampled.
+    fn sample_surface(&self) -> Option<HitData> {
+        None
+    }
+    /// Describes the object for a scene file, referring to its material by `material`.
+    fn to_description(&self, material: String) -> ObjectDescription;
 }
@@ -0,0 +1,181 @@
+use crate::rendering::Ray;
+
+use super::AABB;
+
+/// Most objects a leaf holds before it is split.
+const LEAF_SIZE: usize = 2;
+/// Deep enough for any tree built from median splits.
+const STACK_SIZE: usize = 32;
+
+struct ObjectNode {
+    aabb: AABB,
+    /// First child for inner nodes, the second one follows it. For leaves, the
+    /// first of their entries in `objects`.
+    start: usize,
+    /// Number of objects in a leaf, zero for inner nodes.
+    count: usize,
+}
+
+/// Bounding volume hierarchy over whole scene objects, so a ray only tests
+/// the objects whose boxes it passes through. Meshes keep their own `BVH`
+/// over triangles below this.
+pub struct ObjectBVH {
+    nodes: Vec<ObjectNode>,
+    /// Object indices, grouped by leaf.
+    objects: Vec<usize>,
+}
+
+impl ObjectBVH {
+    /// Builds the tree over `bounds`, each an object index with its world
+    /// space box.
+    pub fn build(mut bounds: Vec<(usize, AABB)>) -> ObjectBVH {
+        let mut bvh = ObjectBVH {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            objects: Vec::with_capacity(bounds.len()),
+        };
+
+        if !bounds.is_empty() {
+            bvh.nodes.push(ObjectNode {
+                aabb: AABB::new(),
+                start: 0,
+                count: 0,
+            });
+            bvh.subdivide(0, &mut bounds);
+        }
+
+        bvh
+    }
+
+    fn subdivide(&mut self, node: usize, items: &mut [(usize, AABB)]) {
+        let aabb = items[1..]
+            .iter()
+            .fold(items[0].1.clone(), |aabb, (_, other)| aabb.union(other));
+
+        if items.len() <= LEAF_SIZE {
+            self.nodes[node] = ObjectNode {
+                aabb,
+                start: self.objects.len(),
+                count: items.len(),
+            };
+            self.objects.extend(items.iter().map(|(i, _)| *i));
+            return;
+        }
+
+        // median split along the axis the centers spread out the most on
+        let centers = AABB::from_points(items.iter().map(|(_, aabb)| aabb.center()))
+            .expect("at least one item");
+        let extent = centers.max - centers.min;
+        let axis = |aabb: &AABB| {
+            let center = aabb.center();
+            if extent.x >= extent.y && extent.x >= extent.z {
+                center.x
+            } else if extent.y >= extent.z {
+                center.y
+            } else {
+                center.z
+            }
+        };
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |a, b| axis(&a.1).total_cmp(&axis(&b.1)));
+
+        let first_child = self.nodes.len();
+        for _ in 0..2 {
+            self.nodes.push(ObjectNode {
+                aabb: AABB::new(),
+                start: 0,
+                count: 0,
+            });
+        }
+        self.nodes[node] = ObjectNode {
+            aabb,
+            start: first_child,
+            count: 0,
+        };
+
+        let (left, right) = items.split_at_mut(mid);
+        self.subdivide(first_child, left);
+        self.subdivide(first_child + 1, right);
+    }
+
+    /// Calls `hit` with every object whose box `ray` enters closer than
+    /// `max_dist` and the closest hit so far. `hit` returns the distance to
+    /// the object's own hit, if any.
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        mut max_dist: f32,
+        mut hit: impl FnMut(usize) -> Option<f32>,
+    ) {
+        self.traverse(ray, max_dist, |objects| {
+            for &object in objects {
+                if let Some(dist) = hit(object) {
+                    max_dist = max_dist.min(dist);
+                }
+            }
+            Some(max_dist)
+        });
+    }
+
+    /// Whether `blocks` returns true for any object whose box `ray` enters
+    /// closer than `max_dist`.
+    pub fn any(&self, ray: &Ray, max_dist: f32, mut blocks: impl FnMut(usize) -> bool) -> bool {
+        let mut blocked = false;
+        self.traverse(ray, max_dist, |objects| {
+            blocked = objects.iter().any(|&object| blocks(object));
+            (!blocked).then_some(max_dist)
+        });
+        blocked
+    }
+
+    /// Walks the leaves whose boxes `ray` enters closer than `max_dist`,
+    /// nearer children first. `leaf` returns the new limit for the remaining
+    /// boxes, or `None` to stop.
+    fn traverse(
+        &self,
+        ray: &Ray,
+        mut max_dist: f32,
+        mut leaf: impl FnMut(&[usize]) -> Option<f32>,
+    ) {
+        let Some(root) = self.nodes.first() else {
+            return;
+        };
+        let Some(root_entry) = root.aabb.entry(ray) else {
+            return;
+        };
+
+        // box entries are in multiples of the direction, hits in world units
+        let scale = ray.direction.length();
+        let mut stack = [(0usize, 0f32); STACK_SIZE];
+        stack[0] = (0, root_entry);
+        let mut len = 1;
+
+        while len > 0 {
+            len -= 1;
+            let (idx, entry) = stack[len];
+            if entry * scale > max_dist {
+                continue;
+            }
+
+            let node = &self.nodes[idx];
+            if node.count > 0 {
+                match leaf(&self.objects[node.start..node.start + node.count]) {
+                    Some(dist) => max_dist = dist,
+                    None => return,
+                }
+                continue;
+            }
+
+            let children = [node.start, node.start + 1]
+                .map(|child| self.nodes[child].aabb.entry(ray).map(|t| (child, t)));
+            let (near, far) = match children {
+                [Some(a), Some(b)] if b.1 < a.1 => (Some(b), Some(a)),
+                [a, b] => (a, b),
+            };
+            // the nearer child goes on top so its hits can prune the other
+            for (child, entry) in [far, near].into_iter().flatten() {
+                stack[len] = (child, entry);
+                len += 1;
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::rendering::{RTMaterial, Ray};
+use crate::scene::description::{arr3, ObjectDescription};
 use crate::scene::{HitData, SceneObject};
-use raylib::math::Vector3;
+use crate::utils::onb;
+use raylib::math::{Vector2, Vector3};
 use std::sync::Arc;
 
 pub struct Plane {
@@ -23,7 +25,14 @@ impl SceneObject for Plane {
     fn intersect(&self, ray: &Ray) -> Option<HitData> {
         let pos = ray_plane_intersection(ray, self.position, self.normal);
         return match pos {
-            Some(hit) => Some(HitData::new(hit, self.normal, Vector3::zero())),
+            Some(hit) => {
+                let mut data = HitData::new(hit, self.normal, Vector3::zero());
+                // world units along two fixed directions in the plane
+                let (tangent, bitangent) = onb(self.normal.normalized());
+                let offset = hit - self.position;
+                data.uv = Vector2::new(offset.dot(tangent), offset.dot(bitangent));
+                Some(data)
+            }
             None => None,
         };
     }
@@ -33,6 +42,14 @@ impl SceneObject for Plane {
     }
 
     fn update(&self, _dt: f32) {}
+
+    fn to_description(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Plane {
+            position: arr3(self.position),
+            normal: arr3(self.normal),
+            material,
+        }
+    }
 }
 
 pub fn ray_plane_intersection(ray: &Ray, position: Vector3, normal: Vector3) -> Option<Vector3> {
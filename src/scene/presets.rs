@@ -0,0 +1,43 @@
+use crate::math::Transform;
+use crate::rendering::{LambertianMaterial, MetalMaterial, RTMaterial};
+use crate::scene::mesh::Mesh;
+use crate::scene::models::{Scene, SceneObject};
+use crate::scene::sphere::Sphere;
+use crate::scene::Plane;
+use raylib::math::{Matrix, Vector3};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub fn init_sphere_scene(scene: &mut Scene) {
+    let white_diffuse_mat: Arc<dyn RTMaterial> = Arc::new(LambertianMaterial::new(Vector3::new(
+        0.5f32, 0.5f32, 0.5f32,
+    )));
+    let red_diffuse_mat: Arc<dyn RTMaterial> =
+        Arc::new(LambertianMaterial::new(Vector3::new(0.65, 0.05, 0.05)));
+
+    let _metal_mat = Arc::new(MetalMaterial::from_fuzz(Vector3::one(), 0.1));
+
+    let bottom_plane: Box<dyn SceneObject> = Box::new(Plane::new(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Arc::clone(&white_diffuse_mat),
+    ));
+
+    let _sphere_b: Box<dyn SceneObject> = Box::new(Sphere::new(
+        Vector3::new(0.0, 0.75, 3.0),
+        0.75,
+        Arc::clone(&red_diffuse_mat),
+    ));
+
+    let t = Matrix::translate(0f32, 3f32, 5f32);
+
+    let model = Mesh::from_obj(
+        "models/dragon_simple.obj",
+        Transform::new(Matrix::rotate_y(PI) * Matrix::scale(10f32, 10f32, 10f32) * t),
+        Arc::clone(&white_diffuse_mat),
+    );
+
+    scene.add_object(bottom_plane);
+    // scene.add_object(sphere_b);
+    scene.add_object(Box::new(model));
+}
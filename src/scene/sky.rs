@@ -0,0 +1,200 @@
+use crate::scene::description::EnvironmentDescription;
+use crate::scene::Environment;
+use crate::utils::{onb, rand_f32, rand_unit_vec, xyy_to_rgb};
+use raylib::math::Vector3;
+use std::f32::consts::PI;
+
+/// Angular radius of the sun disk.
+const SUN_ANGULAR_RADIUS: f32 = 0.2665f32 * PI / 180f32;
+/// Sky luminance comes out of the model in kcd/m², this brings the zenith of
+/// a clear midday sky to roughly 0.5.
+const SKY_SCALE: f32 = 1f32 / 15f32;
+/// Radiance of the sun disk outside the atmosphere in the same units, which
+/// makes the sun around five times brighter than the sky on a clear day.
+const SUN_RADIANCE: f32 = 1e5f32;
+/// How often `sample` aims for the sun disk rather than the whole sky. The
+/// smooth sky is mostly left to BSDF sampling.
+const SUN_SAMPLE_PROB: f32 = 0.9f32;
+
+/// Analytic daylight after Preetham et al., "A Practical Analytic Model for
+/// Daylight", with a sun disk attenuated by the atmosphere. The sun position
+/// is given in degrees, azimuth turning from +z towards +x. `turbidity` goes
+/// from about 2 for a very clear sky to 10 for hazy air.
+pub struct SkyEnvironment {
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    pub turbidity: f32,
+    pub intensity: f32,
+    sun_dir: Vector3,
+    sun_radiance: Vector3,
+    /// Perez coefficients A to E for Y, x and y.
+    perez: [[f32; 5]; 3],
+    /// Zenith Y, x and y divided by the Perez function at the zenith.
+    zenith: [f32; 3],
+}
+
+impl SkyEnvironment {
+    pub fn new(
+        sun_elevation: f32,
+        sun_azimuth: f32,
+        turbidity: f32,
+        intensity: f32,
+    ) -> SkyEnvironment {
+        let (elevation, azimuth) = (sun_elevation.to_radians(), sun_azimuth.to_radians());
+        let sun_dir = Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+
+        // the model breaks down once the sun sets, keep the sky at its dusk colors
+        let theta_s = (PI / 2f32 - elevation).clamp(0f32, PI / 2f32);
+        let t = turbidity;
+
+        let perez = [
+            [
+                0.1787f32 * t - 1.4630f32,
+                -0.3554f32 * t + 0.4275f32,
+                -0.0227f32 * t + 5.3251f32,
+                0.1206f32 * t - 2.5771f32,
+                -0.0670f32 * t + 0.3703f32,
+            ],
+            [
+                -0.0193f32 * t - 0.2592f32,
+                -0.0665f32 * t + 0.0008f32,
+                -0.0004f32 * t + 0.2125f32,
+                -0.0641f32 * t - 0.8989f32,
+                -0.0033f32 * t + 0.0452f32,
+            ],
+            [
+                -0.0167f32 * t - 0.2608f32,
+                -0.0950f32 * t + 0.0092f32,
+                -0.0079f32 * t + 0.2102f32,
+                -0.0441f32 * t - 1.6537f32,
+                -0.0109f32 * t + 0.0529f32,
+            ],
+        ];
+
+        let chi = (4f32 / 9f32 - t / 120f32) * (PI - 2f32 * theta_s);
+        let zenith_y =
+            ((4.0453f32 * t - 4.9710f32) * chi.tan() - 0.2155f32 * t + 2.4192f32).max(0f32);
+
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166f32 * th3 - 0.00375f32 * th2 + 0.00209f32 * th)
+            + t * (-0.02903f32 * th3 + 0.06377f32 * th2 - 0.03202f32 * th + 0.00394f32)
+            + (0.11693f32 * th3 - 0.21196f32 * th2 + 0.06052f32 * th + 0.25886f32);
+        let zenith_yc = t * t * (0.00275f32 * th3 - 0.00610f32 * th2 + 0.00317f32 * th)
+            + t * (-0.04214f32 * th3 + 0.08970f32 * th2 - 0.04153f32 * th + 0.00516f32)
+            + (0.15346f32 * th3 - 0.26756f32 * th2 + 0.06670f32 * th + 0.26688f32);
+
+        let zenith = [zenith_y, zenith_x, zenith_yc];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez_f(&perez[i], 0f32, theta_s));
+
+        SkyEnvironment {
+            sun_elevation,
+            sun_azimuth,
+            turbidity,
+            intensity,
+            sun_dir,
+            sun_radiance: sun_transmittance(theta_s, turbidity) * SUN_RADIANCE,
+            perez,
+            zenith,
+        }
+    }
+
+    fn sun_visible(&self) -> bool {
+        self.sun_dir.y > 0f32
+    }
+
+    fn sky_radiance(&self, dir: Vector3) -> Vector3 {
+        // below the horizon the sky continues with its horizon color
+        let cos_theta = dir.y.max(0.001f32);
+        let theta = cos_theta.acos();
+        let gamma = dir.dot(self.sun_dir).clamp(-1f32, 1f32).acos();
+
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * perez_f(&self.perez[i], theta, gamma));
+
+        xyy_to_rgb(x, y, luminance * SKY_SCALE)
+    }
+
+    fn in_sun(&self, dir: Vector3) -> bool {
+        self.sun_visible() && dir.dot(self.sun_dir) >= SUN_ANGULAR_RADIUS.cos()
+    }
+}
+
+impl Environment for SkyEnvironment {
+    fn radiance(&self, dir: Vector3) -> Vector3 {
+        let mut radiance = self.sky_radiance(dir);
+        if self.in_sun(dir) {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    /// Aims for the sun disk or picks a uniform direction on the sphere for
+    /// the rest of the sky.
+    fn sample(&self) -> Option<(Vector3, f32)> {
+        let dir = if self.sun_visible() && rand_f32() < SUN_SAMPLE_PROB {
+            let cos_max = SUN_ANGULAR_RADIUS.cos();
+            let cos_theta = 1f32 - rand_f32() * (1f32 - cos_max);
+            let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+            let phi = 2f32 * PI * rand_f32();
+
+            let (t, b) = onb(self.sun_dir);
+            t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + self.sun_dir * cos_theta
+        } else {
+            rand_unit_vec()
+        };
+
+        Some((dir, self.pdf(dir)))
+    }
+
+    fn pdf(&self, dir: Vector3) -> f32 {
+        let uniform = 1f32 / (4f32 * PI);
+        if !self.sun_visible() {
+            return uniform;
+        }
+
+        let cone = if self.in_sun(dir) {
+            1f32 / (2f32 * PI * (1f32 - SUN_ANGULAR_RADIUS.cos()))
+        } else {
+            0f32
+        };
+        SUN_SAMPLE_PROB * cone + (1f32 - SUN_SAMPLE_PROB) * uniform
+    }
+
+    fn to_description(&self) -> EnvironmentDescription {
+        EnvironmentDescription::Sky {
+            sun_elevation: self.sun_elevation,
+            sun_azimuth: self.sun_azimuth,
+            turbidity: self.turbidity,
+            intensity: self.intensity,
+        }
+    }
+}
+
+/// Perez et al. sky luminance distribution for a view zenith angle `theta`
+/// and angle `gamma` to the sun.
+fn perez_f(coeffs: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coeffs;
+    (1f32 + a * (b / theta.cos().max(0.001f32)).exp())
+        * (1f32 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+/// Fraction of sunlight left after Rayleigh and aerosol scattering on its way
+/// through the atmosphere, evaluated at wavelengths standing in for R, G and B.
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Vector3 {
+    let theta_deg = theta_s.to_degrees();
+    let air_mass = 1f32 / (theta_s.cos() + 0.15f32 * (93.885f32 - theta_deg).powf(-1.253f32));
+    let beta = 0.04608f32 * turbidity - 0.04586f32;
+    let alpha = 1.3f32;
+
+    let channel = |lambda_um: f32| {
+        let rayleigh = (-air_mass * 0.008735f32 * lambda_um.powf(-4.08f32)).exp();
+        let aerosol = (-air_mass * beta * lambda_um.powf(-alpha)).exp();
+        rayleigh * aerosol
+    };
+
+    Vector3::new(channel(0.65f32), channel(0.57f32), channel(0.475f32))
+}
@@ -1,6 +1,9 @@
 use crate::rendering::{RTMaterial, Ray};
-use crate::scene::{HitData, SceneObject};
-use raylib::math::Vector3;
+use crate::scene::description::{arr3, ObjectDescription};
+use crate::scene::{HitData, SceneObject, AABB};
+use crate::utils::rand_unit_vec;
+use raylib::math::{Vector2, Vector3};
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 pub struct Sphere {
@@ -30,13 +33,17 @@ impl SceneObject for Sphere {
         if disc > 0f32 {
             let t0 = (-b + disc.sqrt()) / (2f32 * a);
             let t1 = (-b - disc.sqrt()) / (2f32 * a);
-            let t = if t0 < t1 { t0 } else { t1 };
+            // the far root is only taken when the ray starts inside the sphere
+            let t = if t1 > 0f32 { t1 } else { t0 };
             if t > 0f32 {
-                Some(HitData::new(
-                    ray.at(t),
-                    (ray.at(t) - self.position).normalized(),
-                    Vector3::zero(),
-                ))
+                let normal = (ray.at(t) - self.position).normalized();
+                let mut hit = HitData::new(ray.at(t), normal, Vector3::zero());
+                // longitude around the y axis, latitude from the top
+                hit.uv = Vector2::new(
+                    0.5f32 + normal.x.atan2(normal.z) / (2f32 * PI),
+                    normal.y.clamp(-1f32, 1f32).acos() / PI,
+                );
+                Some(hit)
             } else {
                 None
             }
@@ -49,7 +56,38 @@ impl SceneObject for Sphere {
         return Arc::clone(&self.material);
     }
 
+    fn area(&self) -> Option<f32> {
+        Some(4f32 * PI * self.radius * self.radius)
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        // the sign of the radius is ignored, `intersect` only ever squares it
+        let r = self.radius.abs();
+        let extent = Vector3::new(r, r, r);
+        Some(AABB::from_bounds(
+            self.position - extent,
+            self.position + extent,
+        ))
+    }
+
+    fn sample_surface(&self) -> Option<HitData> {
+        let normal = rand_unit_vec();
+        Some(HitData::new(
+            self.position + normal * self.radius.abs(),
+            normal,
+            Vector3::zero(),
+        ))
+    }
+
     fn update(&self, _: f32) {
         //todo!()
     }
+
+    fn to_description(&self, material: String) -> ObjectDescription {
+        ObjectDescription::Sphere {
+            center: arr3(self.position),
+            radius: self.radius,
+            material,
+        }
+    }
 }
@@ -22,12 +22,20 @@ impl Clone for Triangle {
 pub struct TriangleHitData {
     pub p: Vector3,
     pub normal: Vector3,
+    pub geometric_normal: Vector3,
     pub bary: Vector3,
+    pub uv: Vector2,
 }
 
 impl TriangleHitData {
     pub fn new(p: Vector3, normal: Vector3, bary: Vector3) -> TriangleHitData {
-        return TriangleHitData { p, normal, bary };
+        return TriangleHitData {
+            p,
+            normal,
+            geometric_normal: normal,
+            bary,
+            uv: Vector2::zero(),
+        };
     }
 }
 
@@ -50,6 +58,20 @@ impl Triangle {
         return sum / 3.0;
     }
 
+    pub fn area(&self) -> f32 {
+        (self.verts[1] - self.verts[0])
+            .cross(self.verts[2] - self.verts[0])
+            .length()
+            * 0.5f32
+    }
+
+    /// Maps two uniform numbers to a point distributed uniformly over the triangle.
+    pub fn sample_point(&self, u1: f32, u2: f32) -> Vector3 {
+        let su = u1.sqrt();
+        let (b0, b1) = (1f32 - su, u2 * su);
+        self.verts[0] * b0 + self.verts[1] * b1 + self.verts[2] * (1f32 - b0 - b1)
+    }
+
     pub fn intersect(&self, ray: &Ray) -> Option<TriangleHitData> {
         let edge1 = self.verts[1] - self.verts[0];
         let edge2 = self.verts[2] - self.verts[0];
@@ -57,7 +79,8 @@ impl Triangle {
         let pvec = ray.direction.cross(edge2);
         let det = edge1.dot(pvec);
 
-        if det < EPSILON {
+        // two sided, rays leaving a closed mesh have to hit its back faces
+        if det.abs() < EPSILON {
             return None;
         }
 
@@ -87,10 +110,20 @@ impl Triangle {
             normal
         };
 
-        Some(TriangleHitData::new(
-            ray.origin + ray.direction.scale_by(t),
-            n,
-            Vector3::new(u, v, 1f32 - u - v),
-        ))
+        let uv = match self.uvs {
+            Some(uvs) => {
+                let uv = uvs[0] * (1f32 - u - v) + uvs[1] * u + uvs[2] * v;
+                Vector2::new(uv.x, uv.y)
+            }
+            None => Vector2::zero(),
+        };
+
+        Some(TriangleHitData {
+            p: ray.origin + ray.direction.scale_by(t),
+            normal: n,
+            geometric_normal: normal,
+            bary: Vector3::new(u, v, 1f32 - u - v),
+            uv,
+        })
     }
 }
@@ -1,14 +1,38 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use raylib::prelude::Vector3;
+use std::cell::RefCell;
 
-pub fn rand_vec3() -> Vector3 {
-    let mut rng = rand::thread_rng();
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Reseeds the random generator of the calling thread. The renderer does this
+/// per pixel when a seed is set so results don't depend on how rayon schedules work.
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn rand_f32() -> f32 {
+    with_rng(|rng| rng.gen::<f32>())
+}
+
+pub fn rand_range(min: f32, max: f32) -> f32 {
+    with_rng(|rng| rng.gen_range(min..max))
+}
 
-    return Vector3::new(
-        rng.gen_range(-1f32..=1f32),
-        rng.gen_range(-1f32..=1f32),
-        rng.gen_range(-1f32..=1f32),
-    );
+pub fn rand_vec3() -> Vector3 {
+    return with_rng(|rng| {
+        Vector3::new(
+            rng.gen_range(-1f32..=1f32),
+            rng.gen_range(-1f32..=1f32),
+            rng.gen_range(-1f32..=1f32),
+        )
+    });
 }
 
 pub fn rand_in_unit_sphere() -> Vector3 {
@@ -35,6 +59,60 @@ pub fn rand_in_hemisphere(normal: Vector3) -> Vector3 {
     };
 }
 
+/// Uniformly distributed point in the unit disk, by rejection.
+pub fn rand_in_unit_disk() -> (f32, f32) {
+    loop {
+        let (x, y) = (rand_range(-1f32, 1f32), rand_range(-1f32, 1f32));
+        if x * x + y * y <= 1f32 {
+            return (x, y);
+        }
+    }
+}
+
+/// Cosine weighted direction in the hemisphere around `normal`, pdf `cos / PI`.
+pub fn rand_cosine_hemisphere(normal: Vector3) -> Vector3 {
+    let (r1, r2) = with_rng(|rng| (rng.gen::<f32>(), rng.gen::<f32>()));
+    let phi = 2f32 * std::f32::consts::PI * r1;
+    let r = r2.sqrt();
+
+    let (t, b) = onb(normal);
+    (t * (r * phi.cos()) + b * (r * phi.sin()) + normal * (1f32 - r2).max(0f32).sqrt()).normalized()
+}
+
+/// Two unit vectors that form an orthonormal basis together with `n`
+/// (Duff et al., "Building an Orthonormal Basis, Revisited").
+pub fn onb(n: Vector3) -> (Vector3, Vector3) {
+    let sign = 1f32.copysign(n.z);
+    let a = -1f32 / (sign + n.z);
+    let b = n.x * n.y * a;
+
+    (
+        Vector3::new(1f32 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
 pub fn reflect(in_vec: Vector3, normal: Vector3) -> Vector3 {
     return in_vec.reflect_from(normal);
 }
+
+/// Relative luminance of a linear Rec. 709 color.
+pub fn luminance(color: Vector3) -> f32 {
+    0.2126f32 * color.x + 0.7152f32 * color.y + 0.0722f32 * color.z
+}
+
+/// Linear sRGB from CIE xyY chromaticity and luminance, negative channels clipped.
+pub fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vector3 {
+    if y <= 0f32 {
+        return Vector3::zero();
+    }
+
+    let cx = x / y * luminance;
+    let cz = (1f32 - x - y) / y * luminance;
+
+    Vector3::new(
+        (3.2406f32 * cx - 1.5372f32 * luminance - 0.4986f32 * cz).max(0f32),
+        (-0.9689f32 * cx + 1.8758f32 * luminance + 0.0415f32 * cz).max(0f32),
+        (0.0557f32 * cx - 0.2040f32 * luminance + 1.0570f32 * cz).max(0f32),
+    )
+}